@@ -0,0 +1,108 @@
+//! Feeds synthetic captures through the slog pipeline (line framing, timestamping
+//! and the output writer) and the TLog decoder, and reports the sustained rates as
+//! baud rates.
+//!
+//! Run with `cargo bench`. The rates are compared to `TARGET_BAUD`, a machine that
+//! keeps up with it keeps up with the fastest adapters.
+
+use chrono::Local;
+use std::fs::{self, File};
+use std::time::{Duration, Instant};
+
+use gesk_log::framing::{Delimiter, LineFramer, Overflow};
+use gesk_log::tlog::{Payload, PayloadType, TLog, TLogDecoder, TLogEvent};
+use gesk_log::utils::timestamped;
+use gesk_log::writer::OutputWriter;
+
+/// Bytes fed through the pipeline.
+const CAPTURE_SIZE: usize = 64 << 20;
+/// Bytes handed over per read, smaller than a real read at these rates to stay on the safe side.
+const CHUNK_SIZE: usize = 512;
+/// Slowest acceptable rate, with headroom over the 3 Mbaud the fastest adapters in use run at.
+const TARGET_BAUD: f64 = 12_000_000.0;
+
+fn capture() -> Vec<u8> {
+    let mut data = Vec::with_capacity(CAPTURE_SIZE + 128);
+    let mut i = 0u64;
+    while data.len() < CAPTURE_SIZE {
+        let line = format!(
+            "I (12345) sensor: sample {i} temp=23.{} rh=41.{}\n",
+            i % 10,
+            i % 7
+        );
+        data.extend_from_slice(line.as_bytes());
+        i += 1;
+    }
+    data
+}
+
+fn tlog_capture() -> Vec<u8> {
+    let mut data = Vec::with_capacity(CAPTURE_SIZE + 128);
+    let mut i = 0u64;
+    while data.len() < CAPTURE_SIZE {
+        let payload = format!("sensor: sample {i} temp=23.{} rh=41.{}", i % 10, i % 7);
+        let packet = TLog::new(payload, PayloadType::Debug).to_packet().unwrap();
+        data.extend_from_slice(&packet);
+        i += 1;
+    }
+    data
+}
+
+/// Runs `pipeline` with a writer to a temporary file and prints the rate it went
+/// through `data` at and how it compares to `TARGET_BAUD`.
+fn run(name: &str, data: &[u8], pipeline: impl FnOnce(&[u8], &OutputWriter)) {
+    let path = std::env::temp_dir().join(format!("gesk-log-bench-{}.txt", std::process::id()));
+    let file = File::create(&path).expect("Unable to create the output file");
+
+    let start = Instant::now();
+    {
+        let writer = OutputWriter::spawn(Some(file), false);
+        pipeline(data, &writer);
+        // Dropping the writer waits for the file to be written.
+    }
+    let elapsed = start.elapsed().as_secs_f64();
+    let _ = fs::remove_file(&path);
+
+    // 10 bits on the wire per byte with 8N1.
+    let baud = data.len() as f64 * 10.0 / elapsed;
+    println!(
+        "{name}: {} MiB in {:.3} s, {:.1} MiB/s, {:.1} Mbaud sustained",
+        data.len() >> 20,
+        elapsed,
+        data.len() as f64 / elapsed / (1 << 20) as f64,
+        baud / 1e6
+    );
+    println!(
+        "{name}: {:.1}x the target of {:.1} Mbaud",
+        baud / TARGET_BAUD,
+        TARGET_BAUD / 1e6
+    );
+}
+
+fn main() {
+    run("slog", &capture(), |data, writer| {
+        let mut framer = LineFramer::new(
+            Delimiter::Bytes(b"\n".to_vec()),
+            64 * 1024,
+            Overflow::Truncate,
+        );
+        for chunk in data.chunks(CHUNK_SIZE) {
+            framer.push(chunk, Local::now(), |line, _| {
+                writer.write(timestamped(line))
+            });
+        }
+    });
+
+    run("tlog", &tlog_capture(), |data, writer| {
+        let mut decoder = TLogDecoder::new(64 * 1024, Overflow::Truncate, Duration::from_secs(5));
+        for chunk in data.chunks(CHUNK_SIZE) {
+            decoder.push(chunk, Local::now(), |event| {
+                if let TLogEvent::Frame(tlog) = event {
+                    if let Payload::Text(text) = tlog.payload() {
+                        writer.write(timestamped(text.as_bytes()))
+                    }
+                }
+            });
+        }
+    });
+}
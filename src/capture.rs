@@ -0,0 +1,297 @@
+use chrono::{DateTime, Local, NaiveDateTime, TimeZone};
+use serde::{Deserialize, Serialize};
+use std::fs::{create_dir_all, File, OpenOptions};
+use std::io::{self, BufReader, Read};
+use std::path::Path;
+use std::thread;
+
+use crate::writer::OutputWriter;
+
+/// Start of every capture file, followed by the length of the [`CaptureInfo`] as a
+/// big endian u32 and the info as JSON. Records follow, see [`Record::to_bytes`].
+const MAGIC: &[u8; 8] = b"GESKCAP1";
+
+/// Directory capture files are written to.
+const CAPTURE_DIR: &str = "captures";
+
+/// Where and how a capture was recorded, stored at the start of the file.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct CaptureInfo {
+    /// Mode the capture was recorded in, `slog` or `tlog`.
+    pub mode: String,
+    pub port: String,
+    pub baud: u32,
+    /// Line settings as shown in the banner, e.g. `8N1`.
+    pub line: String,
+}
+
+impl CaptureInfo {
+    fn to_bytes(&self) -> Vec<u8> {
+        let json = serde_json::to_vec(self).expect("capture info is plain data");
+        [MAGIC, &(json.len() as u32).to_be_bytes()[..], &json].concat()
+    }
+}
+
+/// What a record holds.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum RecordKind {
+    /// Bytes read from the port, as they were returned by a single read.
+    Received = 0,
+    /// Bytes written to the port.
+    Sent = 1,
+    /// A marker message, e.g. that the port was disconnected.
+    Marker = 2,
+}
+
+impl TryFrom<u8> for RecordKind {
+    type Error = String;
+
+    fn try_from(value: u8) -> Result<Self, Self::Error> {
+        match value {
+            0 => Ok(Self::Received),
+            1 => Ok(Self::Sent),
+            2 => Ok(Self::Marker),
+            _ => Err(format!("Unknown capture record kind {value}")),
+        }
+    }
+}
+
+/// A chunk of a capture with the time it was recorded.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Record {
+    pub kind: RecordKind,
+    pub time: DateTime<Local>,
+    pub data: Vec<u8>,
+}
+
+impl Record {
+    /// Length of the kind, time and data length in front of the data.
+    const HEADER_LENGTH: usize = 1 + 8 + 4;
+
+    /// The kind as a byte, the time in microseconds since the Unix epoch as a big
+    /// endian i64, the length of the data as a big endian u32 and the data.
+    pub fn to_bytes(&self) -> Vec<u8> {
+        let mut bytes = Vec::with_capacity(Self::HEADER_LENGTH + self.data.len());
+        bytes.push(self.kind as u8);
+        bytes.extend_from_slice(&self.time.timestamp_micros().to_be_bytes());
+        bytes.extend_from_slice(&(self.data.len() as u32).to_be_bytes());
+        bytes.extend_from_slice(&self.data);
+        bytes
+    }
+}
+
+/// Records what goes over a port to `captures/{name}.gcap`, on the writer thread. An
+/// existing capture isn't written over, the name gets a suffix like `{name}-1` instead.
+pub struct CaptureWriter {
+    writer: OutputWriter,
+}
+
+impl CaptureWriter {
+    pub fn create(name: &str, info: &CaptureInfo) -> Self {
+        if !Path::new(CAPTURE_DIR).exists() {
+            create_dir_all(CAPTURE_DIR).expect("Unable to create dir");
+        }
+
+        let mut suffix = 0;
+        let file = loop {
+            let path = match suffix {
+                0 => format!("{CAPTURE_DIR}/{name}.gcap"),
+                _ => format!("{CAPTURE_DIR}/{name}-{suffix}.gcap"),
+            };
+            match OpenOptions::new().write(true).create_new(true).open(&path) {
+                Ok(file) => {
+                    if suffix > 0 {
+                        eprintln!("\"{CAPTURE_DIR}/{name}.gcap\" exists, capturing to \"{path}\"");
+                    }
+                    break file;
+                }
+                Err(e) if e.kind() == io::ErrorKind::AlreadyExists => suffix += 1,
+                Err(e) => {
+                    eprintln!("Failed to create \"{}\". Error: {}", path, e);
+                    ::std::process::exit(1);
+                }
+            }
+        };
+
+        let writer = OutputWriter::spawn(Some(file), false);
+        writer.write(info.to_bytes());
+        Self { writer }
+    }
+
+    /// Records `data` with the current time, unless there is none.
+    pub fn record(&self, kind: RecordKind, data: &[u8]) {
+        if data.is_empty() {
+            return;
+        }
+        let record = Record {
+            kind,
+            time: Local::now(),
+            data: data.to_vec(),
+        };
+        self.writer.write(record.to_bytes());
+    }
+}
+
+/// Reads the records of a capture file written by [`CaptureWriter`].
+pub struct CaptureReader<R> {
+    reader: R,
+    info: CaptureInfo,
+}
+
+impl CaptureReader<BufReader<File>> {
+    pub fn open(path: &Path) -> Result<Self, String> {
+        let file = File::open(path)
+            .map_err(|e| format!("Failed to open \"{}\". Error: {}", path.display(), e))?;
+        Self::new(BufReader::new(file))
+            .map_err(|e| format!("Invalid capture \"{}\". {}", path.display(), e))
+    }
+}
+
+impl<R: Read> CaptureReader<R> {
+    /// Reads the capture info at the start of `reader`.
+    pub fn new(mut reader: R) -> Result<Self, String> {
+        let mut magic = [0; MAGIC.len()];
+        reader.read_exact(&mut magic).map_err(|e| e.to_string())?;
+        if &magic != MAGIC {
+            return Err("Not a gesk-log capture".to_owned());
+        }
+
+        let mut len = [0; 4];
+        reader.read_exact(&mut len).map_err(|e| e.to_string())?;
+        let json = read_len(&mut reader, u32::from_be_bytes(len)).map_err(|e| e.to_string())?;
+        let info = serde_json::from_slice(&json).map_err(|e| e.to_string())?;
+
+        Ok(Self { reader, info })
+    }
+
+    pub fn info(&self) -> &CaptureInfo {
+        &self.info
+    }
+
+    fn read_record(&mut self) -> io::Result<Option<Record>> {
+        let mut header = [0; Record::HEADER_LENGTH];
+        // A clean end of the file is only possible between records
+        match self.reader.read(&mut header[..1])? {
+            0 => return Ok(None),
+            _ => self.reader.read_exact(&mut header[1..])?,
+        }
+
+        let kind = RecordKind::try_from(header[0])
+            .map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e))?;
+        let micros = i64::from_be_bytes(header[1..9].try_into().unwrap());
+        let time = NaiveDateTime::from_timestamp_micros(micros)
+            .map(|utc| Local.from_utc_datetime(&utc))
+            .ok_or_else(|| io::Error::new(io::ErrorKind::InvalidData, "Invalid record time"))?;
+        let len = u32::from_be_bytes(header[9..13].try_into().unwrap());
+
+        let data = read_len(&mut self.reader, len)?;
+        Ok(Some(Record { kind, time, data }))
+    }
+
+    /// Calls `handle` with every record, waiting between them as long as when they
+    /// were recorded if `realtime`, or as fast as possible otherwise.
+    pub fn replay(mut self, realtime: bool, mut handle: impl FnMut(Record)) -> Result<(), String> {
+        let mut previous: Option<DateTime<Local>> = None;
+
+        while let Some(record) = self
+            .read_record()
+            .map_err(|e| format!("Failed to read the capture. Error: {e}"))?
+        {
+            if let (true, Some(previous)) = (realtime, previous) {
+                if let Ok(delay) = (record.time - previous).to_std() {
+                    thread::sleep(delay);
+                }
+            }
+            previous = Some(record.time);
+            handle(record);
+        }
+
+        Ok(())
+    }
+}
+
+/// Reads `len` bytes from `reader`, growing the buffer as they come in so a corrupt
+/// length ends with the file instead of allocating up to 4 GiB first.
+fn read_len(reader: &mut impl Read, len: u32) -> io::Result<Vec<u8>> {
+    let mut data = Vec::new();
+    reader.take(len.into()).read_to_end(&mut data)?;
+    if data.len() != len as usize {
+        return Err(io::Error::new(
+            io::ErrorKind::UnexpectedEof,
+            "Capture ends in the middle of a record",
+        ));
+    }
+    Ok(data)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_round_trip() {
+        let info = CaptureInfo {
+            mode: "slog".to_owned(),
+            port: "/dev/ttyUSB0".to_owned(),
+            baud: 921600,
+            line: "8N1".to_owned(),
+        };
+        let utc = NaiveDateTime::from_timestamp_micros(1_700_000_000_123_456).unwrap();
+        let time = Local.from_utc_datetime(&utc);
+        let records = vec![
+            Record {
+                kind: RecordKind::Received,
+                time,
+                data: b"boot\n\x1a\x00".to_vec(),
+            },
+            Record {
+                kind: RecordKind::Marker,
+                time: time + chrono::Duration::microseconds(1),
+                data: b"Disconnected".to_vec(),
+            },
+        ];
+
+        let mut bytes = info.to_bytes();
+        for record in &records {
+            bytes.extend_from_slice(&record.to_bytes());
+        }
+
+        let reader = CaptureReader::new(bytes.as_slice()).unwrap();
+        assert_eq!(reader.info(), &info);
+        let mut replayed = Vec::new();
+        reader
+            .replay(false, |record| replayed.push(record))
+            .unwrap();
+        assert_eq!(replayed, records);
+    }
+
+    #[test]
+    fn test_truncated_capture() {
+        assert!(CaptureReader::new(&b"GESKCAP"[..]).is_err());
+
+        let info = CaptureInfo {
+            mode: "tlog".to_owned(),
+            port: "COM3".to_owned(),
+            baud: 115200,
+            line: "8N1".to_owned(),
+        };
+        let mut bytes = info.to_bytes();
+        bytes.extend_from_slice(&[0, 0, 0]);
+        let reader = CaptureReader::new(bytes.as_slice()).unwrap();
+        assert!(reader.replay(false, |_| ()).is_err());
+
+        // A corrupt length fails at the end of the file
+        let mut bytes = info.to_bytes();
+        let record = Record {
+            kind: RecordKind::Received,
+            time: Local::now(),
+            data: b"ok".to_vec(),
+        };
+        let mut record = record.to_bytes();
+        record[9..13].copy_from_slice(&u32::MAX.to_be_bytes());
+        bytes.extend_from_slice(&record);
+        let reader = CaptureReader::new(bytes.as_slice()).unwrap();
+        assert!(reader.replay(false, |_| ()).is_err());
+        assert!(CaptureReader::new(&b"GESKCAP1\xff\xff\xff\xff{}"[..]).is_err());
+    }
+}
@@ -0,0 +1,278 @@
+use clap::ValueEnum;
+use rustyline::error::ReadlineError;
+use rustyline::{
+    Cmd, ConditionalEventHandler, DefaultEditor, Event, EventContext, EventHandler,
+    ExternalPrinter, KeyEvent, RepeatCount,
+};
+use serde::{Deserialize, Serialize};
+use std::io::{self, IsTerminal};
+use std::path::PathBuf;
+use std::sync::mpsc::{self, Receiver, Sender};
+use std::sync::Mutex;
+use std::thread;
+
+use crate::serial::ResetSequence;
+
+/// File the typed lines are kept in between sessions, in the data directory of the user.
+const HISTORY_FILE: &str = "gesk-log/history";
+
+/// Key resetting the target with the configured sequence when pressed with Ctrl.
+const RESET_KEY: char = 'R';
+
+const HELP: &str = "Commands:
+  Ctrl+R             reset the target with the configured sequence
+  :reset [sequence]  reset the target with the given or configured sequence
+  :dtr on|off        set DTR
+  :rts on|off        set RTS
+  :quit              stop capturing
+  :help              show this message";
+
+const TERMINAL_HELP: &str = "Lines not starting with `:` are sent to the device, start them with `::` to send a leading `:`.";
+
+/// Prints above the line being typed while the console is reading, see [`print`].
+static PRINTER: Mutex<Option<Box<dyn ExternalPrinter + Send>>> = Mutex::new(None);
+
+/// Where [`HISTORY_FILE`] is, `None` without a data directory or if it can't be created.
+fn history_file() -> Option<PathBuf> {
+    let path = dirs::data_dir()?.join(HISTORY_FILE);
+    std::fs::create_dir_all(path.parent()?).ok()?;
+    Some(path)
+}
+
+/// Prints `text` without mangling the line being typed on the console.
+pub fn print(text: &str) {
+    match PRINTER.lock().unwrap().as_mut() {
+        Some(printer) => {
+            let _ = printer.print(text.to_owned());
+        }
+        None => print!("{text}"),
+    }
+}
+
+/// What is sent after each line typed in terminal mode.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, ValueEnum, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum LineEnding {
+    #[default]
+    Lf,
+    Cr,
+    Crlf,
+    /// Nothing, only the typed text is sent
+    None,
+}
+
+impl LineEnding {
+    fn as_bytes(&self) -> &'static [u8] {
+        match self {
+            Self::Lf => b"\n",
+            Self::Cr => b"\r",
+            Self::Crlf => b"\r\n",
+            Self::None => b"",
+        }
+    }
+}
+
+/// A command typed on the terminal while capturing.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum ConsoleCommand {
+    /// Runs the given reset sequence, or the configured one.
+    Reset(Option<ResetSequence>),
+    Dtr(bool),
+    Rts(bool),
+    /// Bytes to write to the port, only typed in terminal mode.
+    Send(Vec<u8>),
+    Quit,
+}
+
+/// Parses a line typed on the terminal, `Ok(None)` for lines that need no action.
+/// With a `line_ending` the console is in terminal mode and plain lines are sent.
+fn parse_command(
+    line: &str,
+    line_ending: Option<LineEnding>,
+) -> Result<Option<ConsoleCommand>, String> {
+    if let Some(line_ending) = line_ending {
+        let text = match line.strip_prefix(':') {
+            Some(text) if text.starts_with(':') => Some(text),
+            Some(_) => None,
+            None => Some(line),
+        };
+        if let Some(text) = text {
+            let data = [text.as_bytes(), line_ending.as_bytes()].concat();
+            return Ok(Some(ConsoleCommand::Send(data)));
+        }
+    }
+
+    let line = line.trim();
+    if line.is_empty() {
+        return Ok(None);
+    }
+
+    let Some(command) = line.strip_prefix(':') else {
+        return Err(format!("Unknown input \"{line}\", type :help for commands"));
+    };
+    let (name, arg) = command.split_once(' ').unwrap_or((command, ""));
+    let arg = arg.trim();
+    let level = || match arg {
+        "on" | "1" => Ok(true),
+        "off" | "0" => Ok(false),
+        _ => Err(format!(":{name} takes on or off")),
+    };
+
+    match name {
+        "reset" if arg.is_empty() => Ok(Some(ConsoleCommand::Reset(None))),
+        "reset" => Ok(Some(ConsoleCommand::Reset(Some(arg.parse()?)))),
+        "dtr" => Ok(Some(ConsoleCommand::Dtr(level()?))),
+        "rts" => Ok(Some(ConsoleCommand::Rts(level()?))),
+        "quit" | "q" => Ok(Some(ConsoleCommand::Quit)),
+        "help" => {
+            eprintln!("{HELP}");
+            if line_ending.is_some() {
+                eprintln!("{TERMINAL_HELP}");
+            }
+            Ok(None)
+        }
+        _ => Err(format!(
+            "Unknown command \":{name}\", type :help for commands"
+        )),
+    }
+}
+
+/// Reads commands from the terminal on a separate thread, so they can be picked
+/// up between reads from the port. With a `line_ending` typed lines are sent to
+/// the device, see [`ConsoleCommand::Send`].
+///
+/// Only an `interactive` run on a terminal gets the line editor with history, piped
+/// input is read line by line and a non-interactive run doesn't read stdin at all.
+pub fn spawn_console(
+    line_ending: Option<LineEnding>,
+    interactive: bool,
+) -> Receiver<ConsoleCommand> {
+    let (tx, rx) = mpsc::channel();
+
+    if !interactive {
+        return rx;
+    }
+    if !io::stdin().is_terminal() {
+        thread::spawn(move || {
+            for line in io::stdin().lines() {
+                let Ok(line) = line else {
+                    break;
+                };
+                if !send_command(&tx, &line, line_ending) {
+                    break;
+                }
+            }
+        });
+        return rx;
+    }
+
+    thread::spawn(move || {
+        let mut editor = match DefaultEditor::new() {
+            Ok(editor) => editor,
+            Err(e) => {
+                eprintln!("Failed to read from the terminal. Error: {e}");
+                return;
+            }
+        };
+        let history = history_file();
+        if let Some(history) = &history {
+            let _ = editor.load_history(history);
+        }
+        if let Ok(printer) = editor.create_external_printer() {
+            *PRINTER.lock().unwrap() = Some(Box::new(printer));
+        }
+        editor.bind_sequence(
+            KeyEvent::ctrl(RESET_KEY),
+            EventHandler::Conditional(Box::new(ResetKey(tx.clone()))),
+        );
+        let prompt = if line_ending.is_some() { "TX> " } else { "" };
+
+        loop {
+            let line = match editor.readline(prompt) {
+                Ok(line) => line,
+                // Ctrl+C is caught while the line is read, so stop as it would have.
+                Err(ReadlineError::Interrupted) => {
+                    let _ = tx.send(ConsoleCommand::Quit);
+                    break;
+                }
+                Err(_) => break,
+            };
+            if !line.trim().is_empty() {
+                let _ = editor.add_history_entry(line.as_str());
+                if let Some(history) = &history {
+                    let _ = editor.save_history(history);
+                }
+            }
+
+            if !send_command(&tx, &line, line_ending) {
+                break;
+            }
+        }
+
+        *PRINTER.lock().unwrap() = None;
+    });
+
+    rx
+}
+
+/// Sends a reset on [`RESET_KEY`], leaving the line being typed as it is.
+struct ResetKey(Sender<ConsoleCommand>);
+
+impl ConditionalEventHandler for ResetKey {
+    fn handle(&self, _: &Event, _: RepeatCount, _: bool, _: &EventContext) -> Option<Cmd> {
+        let _ = self.0.send(ConsoleCommand::Reset(None));
+        Some(Cmd::Noop)
+    }
+}
+
+/// Sends the command typed as `line` to `tx`, returning false once nothing receives
+/// commands anymore.
+fn send_command(tx: &Sender<ConsoleCommand>, line: &str, line_ending: Option<LineEnding>) -> bool {
+    match parse_command(line, line_ending) {
+        Ok(Some(command)) => return tx.send(command).is_ok(),
+        Ok(None) => (),
+        Err(e) => eprintln!("{e}"),
+    }
+    true
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_terminal_lines() {
+        assert_eq!(
+            parse_command("AT+RST", Some(LineEnding::Crlf)),
+            Ok(Some(ConsoleCommand::Send(b"AT+RST\r\n".to_vec())))
+        );
+        assert_eq!(
+            parse_command("::escaped", Some(LineEnding::Lf)),
+            Ok(Some(ConsoleCommand::Send(b":escaped\n".to_vec())))
+        );
+        assert_eq!(
+            parse_command("", Some(LineEnding::Cr)),
+            Ok(Some(ConsoleCommand::Send(b"\r".to_vec())))
+        );
+        assert_eq!(
+            parse_command(":rts on", Some(LineEnding::Lf)),
+            Ok(Some(ConsoleCommand::Rts(true)))
+        );
+    }
+
+    #[test]
+    fn test_commands() {
+        assert_eq!(
+            parse_command(":reset", None),
+            Ok(Some(ConsoleCommand::Reset(None)))
+        );
+        assert_eq!(
+            parse_command(":dtr off", None),
+            Ok(Some(ConsoleCommand::Dtr(false)))
+        );
+        assert_eq!(parse_command(":quit", None), Ok(Some(ConsoleCommand::Quit)));
+        assert_eq!(parse_command("  ", None), Ok(None));
+        assert!(parse_command("AT+RST", None).is_err());
+        assert!(parse_command(":dtr maybe", None).is_err());
+    }
+}
@@ -0,0 +1,330 @@
+use object::{Object, ObjectSection};
+use std::fmt::Write;
+use std::path::Path;
+
+/// Section of the firmware ELF file holding the format strings of deferred payloads.
+pub const FORMAT_SECTION: &str = ".tlog_fmt";
+
+/// Largest width or precision of a conversion, so a corrupt format string can't make
+/// a huge line.
+const MAX_WIDTH: usize = 256;
+
+/// Format strings of deferred payloads, NUL terminated one after the other, as found
+/// in the [`FORMAT_SECTION`] of the firmware.
+///
+/// A deferred payload is the big endian u16 offset of its format string in the
+/// section followed by its arguments. Format strings are printf-like, with
+/// conversions written `%[-0][width][.precision][length]conversion`, a width and
+/// precision of at most 256:
+/// - `d`, `i` signed, `u`, `x`, `X`, `o` unsigned integers of 4 bytes, 1 byte with
+///   `hh`, 2 with `h`, 4 with `l` and 8 with `ll`;
+/// - `f`, `e`, `g` a 4 byte float, 8 bytes with `l`, printed like in C;
+/// - `c` a single byte char, `p` a 4 byte address;
+/// - `s` a string, a byte with its length followed by its bytes;
+/// - `%%` a percent sign.
+///
+/// Arguments are big endian like the rest of the frame.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct FormatTable {
+    strings: Vec<u8>,
+}
+
+impl FormatTable {
+    pub fn new(strings: Vec<u8>) -> Self {
+        Self { strings }
+    }
+
+    /// Reads the format strings from the [`FORMAT_SECTION`] of the ELF file at `path`.
+    pub fn from_elf(path: &Path) -> Result<Self, String> {
+        let data = std::fs::read(path)
+            .map_err(|e| format!("Failed to open \"{}\". Error: {}", path.display(), e))?;
+        let file = object::File::parse(&*data)
+            .map_err(|e| format!("Invalid ELF file \"{}\". {}", path.display(), e))?;
+        let section = file
+            .section_by_name(FORMAT_SECTION)
+            .ok_or_else(|| format!("No {FORMAT_SECTION} section in \"{}\"", path.display()))?;
+        let strings = section
+            .data()
+            .map_err(|e| format!("Failed to read {FORMAT_SECTION}. Error: {e}"))?;
+        Ok(Self::new(strings.to_vec()))
+    }
+
+    /// The format string at `index`, the offset of its first byte.
+    fn format_string(&self, index: u16) -> Result<&str, String> {
+        let start = usize::from(index);
+        let Some(rest) = self.strings.get(start..).filter(|rest| !rest.is_empty()) else {
+            return Err(format!("No format string at {index}"));
+        };
+        let end = memchr::memchr(0, rest).unwrap_or(rest.len());
+        std::str::from_utf8(&rest[..end])
+            .map_err(|_| format!("Format string at {index} is not valid UTF-8"))
+    }
+
+    /// The text of a deferred payload with the format string at `index`.
+    pub fn render(&self, index: u16, args: &[u8]) -> Result<String, String> {
+        let format = self.format_string(index)?;
+        let mut args = Args(args);
+        let mut text = String::with_capacity(format.len());
+
+        let mut chars = format.chars().peekable();
+        while let Some(c) = chars.next() {
+            if c != '%' {
+                text.push(c);
+                continue;
+            }
+            if chars.peek() == Some(&'%') {
+                chars.next();
+                text.push('%');
+                continue;
+            }
+
+            let mut spec = Spec::default();
+            while let Some(&flag @ ('-' | '0')) = chars.peek() {
+                match flag {
+                    '-' => spec.left = true,
+                    _ => spec.zero = true,
+                }
+                chars.next();
+            }
+            let too_wide = || format!("Width over {MAX_WIDTH} in format string at {index}");
+            spec.width = number(&mut chars).ok_or_else(too_wide)?;
+            if chars.peek() == Some(&'.') {
+                chars.next();
+                spec.precision = Some(number(&mut chars).ok_or_else(too_wide)?);
+            }
+            let mut size = 4;
+            let mut wide = false;
+            while let Some(&length @ ('h' | 'l')) = chars.peek() {
+                chars.next();
+                (size, wide) = match (length, size, wide) {
+                    ('h', 4, _) => (2, false),
+                    ('h', _, _) => (1, false),
+                    ('l', _, false) => (4, true),
+                    _ => (8, true),
+                };
+            }
+
+            let Some(conversion) = chars.next() else {
+                return Err(format!("Format string at {index} ends in a conversion"));
+            };
+            let value = match conversion {
+                'd' | 'i' => args.signed(size)?.to_string(),
+                'u' => args.unsigned(size)?.to_string(),
+                'x' => format!("{:x}", args.unsigned(size)?),
+                'X' => format!("{:X}", args.unsigned(size)?),
+                'o' => format!("{:o}", args.unsigned(size)?),
+                'f' | 'e' | 'g' => {
+                    // `%f` is a float and `%lf` a double, unlike in C
+                    let value = match wide {
+                        true => f64::from_bits(args.unsigned(8)?),
+                        false => f64::from(f32::from_bits(args.unsigned(4)? as u32)),
+                    };
+                    let precision = spec.precision.unwrap_or(6);
+                    match conversion {
+                        _ if !value.is_finite() => value.to_string().to_lowercase(),
+                        'f' => format!("{value:.precision$}"),
+                        'e' => exponential(value, precision),
+                        _ => general(value, precision),
+                    }
+                }
+                'c' => char::from(args.take(1)?[0]).to_string(),
+                'p' => format!("0x{:08x}", args.unsigned(4)?),
+                's' => {
+                    let len = args.take(1)?[0];
+                    let string = String::from_utf8_lossy(args.take(len.into())?).into_owned();
+                    match spec.precision {
+                        Some(precision) => string.chars().take(precision).collect(),
+                        None => string,
+                    }
+                }
+                conversion => {
+                    return Err(format!(
+                        "Unknown conversion %{conversion} in format string at {index}"
+                    ))
+                }
+            };
+            spec.pad(&mut text, &value);
+        }
+
+        if !args.0.is_empty() {
+            return Err(format!("{} bytes of arguments left over", args.0.len()));
+        }
+        Ok(text)
+    }
+}
+
+/// Flags, width and precision of a conversion.
+#[derive(Debug, Default)]
+struct Spec {
+    left: bool,
+    zero: bool,
+    width: usize,
+    precision: Option<usize>,
+}
+
+impl Spec {
+    fn pad(&self, text: &mut String, value: &str) {
+        let width = self.width;
+        let _ = match (self.left, self.zero) {
+            (true, _) => write!(text, "{value:<width$}"),
+            (false, true) => match value.strip_prefix('-') {
+                Some(digits) => write!(text, "-{digits:0>0$}", width.saturating_sub(1)),
+                None => write!(text, "{value:0>width$}"),
+            },
+            (false, false) => write!(text, "{value:>width$}"),
+        };
+    }
+}
+
+/// The number at the start of `chars`, 0 if there is none and `None` if it is over
+/// [`MAX_WIDTH`].
+fn number(chars: &mut std::iter::Peekable<std::str::Chars>) -> Option<usize> {
+    let mut number = 0usize;
+    while let Some(digit) = chars.peek().and_then(|c| c.to_digit(10)) {
+        chars.next();
+        number = number.checked_mul(10)?.checked_add(digit as usize)?;
+    }
+    (number <= MAX_WIDTH).then_some(number)
+}
+
+/// Finite `value` like C's `%.{precision}e`, with a sign and at least two digits in
+/// the exponent.
+fn exponential(value: f64, precision: usize) -> String {
+    let text = format!("{value:.precision$e}");
+    let (mantissa, exponent) = text.split_once('e').unwrap_or((&text, "0"));
+    let exponent: i32 = exponent.parse().unwrap_or(0);
+    let sign = if exponent < 0 { '-' } else { '+' };
+    format!("{mantissa}e{sign}{:02}", exponent.abs())
+}
+
+/// Finite `value` like C's `%.{precision}g`, as `%e` or `%f` with `precision`
+/// significant digits, whichever C picks, without trailing zeros.
+fn general(value: f64, precision: usize) -> String {
+    let precision = precision.max(1);
+    let text = exponential(value, precision - 1);
+    let exponent: i64 = text
+        .split_once('e')
+        .and_then(|(_, exponent)| exponent.parse().ok())
+        .unwrap_or(0);
+    let (mut mantissa, exponent) = match usize::try_from(exponent + 4) {
+        Ok(shifted) if shifted < precision + 4 => {
+            let decimals = (precision as i64 - 1 - exponent) as usize;
+            (format!("{value:.decimals$}"), String::new())
+        }
+        _ => {
+            let (mantissa, exponent) = text.split_at(text.find('e').unwrap_or(text.len()));
+            (mantissa.to_owned(), exponent.to_owned())
+        }
+    };
+    if mantissa.contains('.') {
+        mantissa.truncate(mantissa.trim_end_matches('0').trim_end_matches('.').len());
+    }
+    mantissa + &exponent
+}
+
+/// Arguments of a deferred payload not formatted yet.
+struct Args<'a>(&'a [u8]);
+
+impl<'a> Args<'a> {
+    fn take(&mut self, len: usize) -> Result<&'a [u8], String> {
+        if self.0.len() < len {
+            return Err("Too few bytes of arguments for the format string".to_owned());
+        }
+        let (taken, rest) = self.0.split_at(len);
+        self.0 = rest;
+        Ok(taken)
+    }
+
+    fn unsigned(&mut self, size: usize) -> Result<u64, String> {
+        Ok(self
+            .take(size)?
+            .iter()
+            .fold(0, |value, &byte| value << 8 | u64::from(byte)))
+    }
+
+    fn signed(&mut self, size: usize) -> Result<i64, String> {
+        let value = self.unsigned(size)?;
+        // Sign extend from the top bit of the argument
+        let shift = 64 - size * 8;
+        Ok(((value << shift) as i64) >> shift)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_render() {
+        let table =
+            FormatTable::new(b"boot\0temp=%d.%02u %s\0%-4x|%5.1f|%lld|%hhd|%c|%%\0".to_vec());
+        assert_eq!(table.render(0, b"").unwrap(), "boot");
+        assert_eq!(
+            table
+                .render(
+                    5,
+                    &[0xFF, 0xFF, 0xFF, 0xFE, 0, 0, 0, 5, 3, b'a', b'b', b'c']
+                )
+                .unwrap(),
+            "temp=-2.05 abc"
+        );
+
+        let mut args = vec![0, 0, 0, 0xAB];
+        args.extend_from_slice(&1.25f32.to_bits().to_be_bytes());
+        args.extend_from_slice(&(-3i64).to_be_bytes());
+        args.extend_from_slice(&[0x80, b'Z']);
+        assert_eq!(table.render(21, &args).unwrap(), "ab  |  1.2|-3|-128|Z|%");
+    }
+
+    #[test]
+    fn test_render_floats() {
+        let table = FormatTable::new(b"%e|%.2le|%le|%g\0%lg|%lg|%lg|%lg|%.3lg|%.0lg\0".to_vec());
+        let mut args = Vec::new();
+        args.extend_from_slice(&1.25f32.to_bits().to_be_bytes());
+        args.extend_from_slice(&1234.5f64.to_bits().to_be_bytes());
+        args.extend_from_slice(&(-0.00042f64).to_bits().to_be_bytes());
+        args.extend_from_slice(&f32::INFINITY.to_bits().to_be_bytes());
+        assert_eq!(
+            table.render(0, &args).unwrap(),
+            "1.250000e+00|1.23e+03|-4.200000e-04|inf"
+        );
+
+        let args: Vec<u8> = [0.0001, 0.00001, 123456.0, 1234567.0, 4.56789, 26.0]
+            .iter()
+            .flat_map(|value: &f64| value.to_bits().to_be_bytes())
+            .collect();
+        assert_eq!(
+            table.render(16, &args).unwrap(),
+            "0.0001|1e-05|123456|1.23457e+06|4.57|3e+01"
+        );
+    }
+
+    #[test]
+    fn test_render_errors() {
+        let table = FormatTable::new(b"n=%d\0%q\0".to_vec());
+        assert!(table.render(0, &[0, 0]).is_err());
+        assert!(table.render(0, &[0, 0, 0, 0, 0]).is_err());
+        assert!(table.render(5, &[]).is_err());
+        assert!(table.render(8, &[]).is_err());
+        assert!(table.render(400, &[]).is_err());
+
+        // Widths that would overflow or make a huge line
+        let table = FormatTable::new(b"%99999999999999999999999d\0%.999999999d\0%256d\0".to_vec());
+        assert!(table.render(0, &[0, 0, 0, 1]).is_err());
+        assert!(table.render(26, &[0, 0, 0, 1]).is_err());
+        assert_eq!(table.render(39, &[0, 0, 0, 1]).unwrap().len(), 256);
+    }
+
+    #[cfg(target_os = "linux")]
+    #[test]
+    fn test_from_elf() {
+        #[link_section = ".tlog_fmt"]
+        #[used]
+        static FORMATS: [u8; 10] = *b"up %u s\0x\0";
+
+        // Reading the strings keeps the section in the test binary
+        assert_eq!(std::hint::black_box(&FORMATS)[0], b'u');
+        let table = FormatTable::from_elf(&std::env::current_exe().unwrap()).unwrap();
+        assert_eq!(table.render(0, &[0, 0, 1, 0]).unwrap(), "up 256 s");
+    }
+}
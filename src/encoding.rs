@@ -0,0 +1,139 @@
+use clap::ValueEnum;
+use serde::{Deserialize, Serialize};
+use std::borrow::Cow;
+use std::fmt::Write;
+use std::str::Utf8Error;
+
+/// Bytes per row of a hex dump.
+const HEX_ROW: usize = 16;
+
+/// How received bytes are shown on the terminal and written to the output file.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, ValueEnum, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Encoding {
+    /// UTF-8, invalid data is written to the file as received but not printed
+    #[default]
+    Utf8,
+    /// UTF-8 with invalid bytes replaced by �
+    Lossy,
+    /// Every byte is the Latin-1 char of the same value
+    Latin1,
+    /// UTF-8 with control chars and invalid bytes escaped like `\x1b`
+    Escaped,
+    /// Offset, hex bytes and ASCII in rows of 16 bytes
+    Hex,
+}
+
+impl Encoding {
+    /// `data` as text, failing only for invalid data in strict UTF-8.
+    pub fn to_text(&self, data: &[u8]) -> Result<String, Utf8Error> {
+        Ok(match self {
+            Self::Utf8 => std::str::from_utf8(data)?.to_owned(),
+            Self::Lossy => String::from_utf8_lossy(data).into_owned(),
+            Self::Latin1 => data.iter().map(|&byte| byte as char).collect(),
+            Self::Escaped => escape(data),
+            Self::Hex => hex_dump(data),
+        })
+    }
+
+    /// `data` ready to be displayed and written. Strict UTF-8 leaves it as it is,
+    /// so invalid data still ends up in the output file.
+    pub fn render<'a>(&self, data: &'a [u8]) -> Cow<'a, [u8]> {
+        match self.to_text(data) {
+            Ok(text) if *self != Self::Utf8 => Cow::Owned(text.into_bytes()),
+            _ => Cow::Borrowed(data),
+        }
+    }
+
+    /// The parts of `text` in this encoding shown on lines of their own, each with its
+    /// own timestamp: the rows of a hex dump, or the whole text otherwise.
+    pub fn rows<'a>(&self, text: &'a [u8]) -> Vec<&'a [u8]> {
+        match self {
+            Self::Hex => text.split(|&byte| byte == b'\n').collect(),
+            _ => vec![text],
+        }
+    }
+}
+
+fn escape(data: &[u8]) -> String {
+    let mut escaped = String::with_capacity(data.len());
+    for chunk in data.utf8_chunks() {
+        for c in chunk.valid().chars() {
+            match c {
+                '\\' => escaped.push_str("\\\\"),
+                '\t' => escaped.push_str("\\t"),
+                '\r' => escaped.push_str("\\r"),
+                '\n' => escaped.push_str("\\n"),
+                c if c.is_control() && (c as u32) < 0x100 => {
+                    let _ = write!(escaped, "\\x{:02x}", c as u32);
+                }
+                c => escaped.push(c),
+            }
+        }
+        for byte in chunk.invalid() {
+            let _ = write!(escaped, "\\x{byte:02x}");
+        }
+    }
+    escaped
+}
+
+fn hex_dump(data: &[u8]) -> String {
+    let mut dump = String::new();
+    for (i, row) in data.chunks(HEX_ROW).enumerate() {
+        if i > 0 {
+            dump.push('\n');
+        }
+        let _ = write!(dump, "{:08x} ", i * HEX_ROW);
+        for (j, byte) in row.iter().enumerate() {
+            // An extra space splits the row in halves of 8 bytes
+            let gap = if j == HEX_ROW / 2 { "  " } else { " " };
+            let _ = write!(dump, "{gap}{byte:02x}");
+        }
+        let missing = HEX_ROW - row.len();
+        let padding = missing * 3 + usize::from(row.len() <= HEX_ROW / 2);
+        let ascii: String = row
+            .iter()
+            .map(|&byte| match byte {
+                0x20..=0x7e => byte as char,
+                _ => '.',
+            })
+            .collect();
+        let _ = write!(dump, "{:padding$}  |{ascii}|", "");
+    }
+    dump
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_text_encodings() {
+        let data = b"caf\xc3\xa9 \xff\x1b[0m\n";
+        assert!(Encoding::Utf8.to_text(data).is_err());
+        assert_eq!(
+            Encoding::Lossy.to_text(data).unwrap(),
+            "café \u{FFFD}\x1b[0m\n"
+        );
+        assert_eq!(Encoding::Latin1.to_text(data).unwrap(), "cafÃ© ÿ\x1b[0m\n");
+        assert_eq!(
+            Encoding::Escaped.to_text(data).unwrap(),
+            "café \\xff\\x1b[0m\\n"
+        );
+    }
+
+    #[test]
+    fn test_hex_dump() {
+        assert_eq!(
+            Encoding::Hex.to_text(b"0123456789abcdef\x00\xff").unwrap(),
+            "00000000  30 31 32 33 34 35 36 37  38 39 61 62 63 64 65 66  |0123456789abcdef|\n\
+             00000010  00 ff                                             |..|"
+        );
+    }
+
+    #[test]
+    fn test_render_strict() {
+        assert_eq!(Encoding::Utf8.render(b"\xff"), Cow::Borrowed(b"\xff"));
+        assert_eq!(Encoding::Latin1.render(b"\xff").as_ref(), "ÿ".as_bytes());
+    }
+}
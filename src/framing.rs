@@ -0,0 +1,442 @@
+use chrono::{DateTime, Local};
+use clap::ValueEnum;
+use memchr::memmem;
+use regex::bytes::Regex;
+use serde::{Deserialize, Serialize};
+use std::fmt::Display;
+use std::time::Duration;
+
+/// Tag appended to lines and frames cut at the maximum length.
+const TRUNCATED_TAG: &str = " \x1b[0m\x1b[33m[truncated]\x1b[0m";
+
+/// Tags `line`, a rendered line ending in a newline, as truncated.
+pub fn tag_truncated(mut line: Vec<u8>) -> Vec<u8> {
+    if line.last() == Some(&b'\n') {
+        line.pop();
+    }
+    line.extend_from_slice(TRUNCATED_TAG.as_bytes());
+    line.push(b'\n');
+    line
+}
+
+/// What happens to a line or frame longer than the maximum length.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, ValueEnum, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Overflow {
+    /// Emit the first bytes, tagged as truncated
+    #[default]
+    Truncate,
+    /// Discard it entirely
+    Drop,
+}
+
+/// Limit on the bytes buffered for a single line or frame, as given on the
+/// command line or in a profile.
+#[derive(Debug, Clone, Default, clap::Args, Serialize, Deserialize)]
+#[serde(default)]
+pub struct LengthArgs {
+    /// Longest line or frame buffered, in bytes, longer ones are handled as set by --overflow
+    #[arg(long, value_parser = clap::value_parser!(u64).range(1..))]
+    #[serde(skip_serializing_if = "Option::is_none")]
+    max_length: Option<u64>,
+
+    /// What to do with lines or frames longer than --max-length [default: truncate]
+    #[arg(long)]
+    #[serde(skip_serializing_if = "Option::is_none")]
+    overflow: Option<Overflow>,
+}
+
+impl LengthArgs {
+    /// Fills the values not given on the command line from `profile`.
+    pub fn merge(self, profile: Self) -> Self {
+        Self {
+            max_length: self.max_length.or(profile.max_length),
+            overflow: self.overflow.or(profile.overflow),
+        }
+    }
+
+    /// The maximum length, `default` if not set.
+    pub fn max_length(&self, default: usize) -> usize {
+        self.max_length
+            .map_or(default, |max_length| max_length as usize)
+    }
+
+    pub fn overflow(&self) -> Overflow {
+        self.overflow.unwrap_or_default()
+    }
+}
+
+/// How often lines or frames went over the maximum length in a session.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub struct OverflowCounts {
+    pub truncated: u64,
+    pub dropped: u64,
+}
+
+impl Display for OverflowCounts {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{} truncated, {} dropped", self.truncated, self.dropped)
+    }
+}
+
+/// Why a line was emitted.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum LineEnd {
+    /// The delimiter was received.
+    Delimiter,
+    /// The line reached the maximum length, the rest follows as a new line.
+    Truncated,
+    /// No byte arrived for the idle gap, the line started at the given time.
+    Idle(DateTime<Local>),
+}
+
+/// What lines are split on.
+#[derive(Debug, Clone)]
+pub enum Delimiter {
+    /// A fixed sequence of one or more bytes, e.g. `\r\n`.
+    Bytes(Vec<u8>),
+    /// The leftmost match of a pattern in the bytes received so far. A pattern that
+    /// could match more, like `> *`, matches what has arrived when it is searched.
+    Regex(Regex),
+    /// Nothing, lines only end on idle gaps, see [`LineFramer::flush_idle`], or at the
+    /// maximum length.
+    None,
+}
+
+impl Delimiter {
+    /// A delimiter matching `pattern`, which must not match an empty string.
+    pub fn regex(pattern: &str) -> Result<Self, String> {
+        let regex = Regex::new(pattern).map_err(|e| format!("Invalid delimiter regex. {e}"))?;
+        if regex.is_match(b"") {
+            return Err(format!(
+                "Delimiter regex \"{pattern}\" must not match an empty string"
+            ));
+        }
+        Ok(Self::Regex(regex))
+    }
+
+    /// Start and end of the first delimiter in `data`, searching from `from`.
+    fn find(&self, data: &[u8], from: usize) -> Option<(usize, usize)> {
+        match self {
+            Self::Bytes(bytes) => {
+                memmem::find(&data[from..], bytes).map(|pos| (from + pos, from + pos + bytes.len()))
+            }
+            Self::Regex(regex) => regex
+                .find_at(data, from)
+                .map(|found| (found.start(), found.end())),
+            Self::None => None,
+        }
+    }
+}
+
+/// Splits received bytes into lines on a [`Delimiter`]. Byte delimiters are
+/// searched for in each byte once, so lines split over many reads cost no more
+/// than lines received in one.
+///
+/// At most `max_length` bytes of a line are buffered. Longer lines are emitted in
+/// pieces tagged [`LineEnd::Truncated`], or discarded up to the next delimiter,
+/// depending on the [`Overflow`] policy.
+#[derive(Debug, Clone)]
+pub struct LineFramer {
+    delimiter: Delimiter,
+    keep_delimiter: bool,
+    max_length: usize,
+    overflow: Overflow,
+    /// Start of the line being received.
+    pending: Vec<u8>,
+    /// Where the search for the delimiter in `pending` continues.
+    scanned: usize,
+    /// Whether the line being received is over the maximum length and dropped.
+    discarding: bool,
+    /// When the first byte of `pending` arrived.
+    started: Option<DateTime<Local>>,
+    /// When the last byte arrived.
+    last_received: Option<DateTime<Local>>,
+    counts: OverflowCounts,
+}
+
+impl LineFramer {
+    pub fn new(delimiter: Delimiter, max_length: usize, overflow: Overflow) -> Self {
+        assert!(max_length > 0, "max_length must not be 0");
+        Self {
+            delimiter,
+            keep_delimiter: false,
+            max_length,
+            overflow,
+            pending: Vec::new(),
+            scanned: 0,
+            discarding: false,
+            started: None,
+            last_received: None,
+            counts: OverflowCounts::default(),
+        }
+    }
+
+    /// Ends emitted lines with their delimiter instead of stripping it.
+    pub fn keep_delimiter(mut self, keep_delimiter: bool) -> Self {
+        self.keep_delimiter = keep_delimiter;
+        self
+    }
+
+    /// Feeds `chunk`, received at `received`, to the framer, calling `emit` with
+    /// every line it completes.
+    pub fn push(
+        &mut self,
+        chunk: &[u8],
+        received: DateTime<Local>,
+        mut emit: impl FnMut(&[u8], LineEnd),
+    ) {
+        if chunk.is_empty() {
+            return;
+        }
+        self.last_received = Some(received);
+        self.pending.extend_from_slice(chunk);
+        let mut start = 0;
+
+        loop {
+            let found = self.delimiter.find(&self.pending, self.scanned.max(start));
+            // The last bytes may be the start of a delimiter still arriving
+            let content_end = found.map_or_else(
+                || match &self.delimiter {
+                    Delimiter::Bytes(bytes) => (self.pending.len() + 1)
+                        .saturating_sub(bytes.len())
+                        .max(start),
+                    Delimiter::Regex(_) | Delimiter::None => self.pending.len(),
+                },
+                |(end, _)| end,
+            );
+
+            while !self.discarding && content_end - start > self.max_length {
+                match self.overflow {
+                    Overflow::Truncate => {
+                        emit(
+                            &self.pending[start..start + self.max_length],
+                            LineEnd::Truncated,
+                        );
+                        self.counts.truncated += 1;
+                        start += self.max_length;
+                    }
+                    Overflow::Drop => {
+                        self.discarding = true;
+                        self.counts.dropped += 1;
+                    }
+                }
+            }
+
+            let Some((end, delimiter_end)) = found else {
+                break;
+            };
+            if !self.discarding {
+                let line_end = if self.keep_delimiter {
+                    delimiter_end
+                } else {
+                    end
+                };
+                emit(&self.pending[start..line_end], LineEnd::Delimiter);
+            }
+            self.discarding = false;
+            start = delimiter_end;
+        }
+
+        // Only what may still be part of a delimiter is kept of a line being dropped.
+        let lookbehind = match &self.delimiter {
+            Delimiter::Bytes(bytes) => bytes.len() - 1,
+            Delimiter::Regex(_) => self.max_length,
+            Delimiter::None => 0,
+        };
+        if self.discarding {
+            start = start.max(self.pending.len().saturating_sub(lookbehind));
+        }
+        self.scanned = match &self.delimiter {
+            Delimiter::Bytes(_) | Delimiter::None => self.pending.len().saturating_sub(lookbehind),
+            // A match may start anywhere in the line
+            Delimiter::Regex(_) => start,
+        }
+        .max(start)
+            - start;
+
+        // The rest of the chunk starts a new line if anything was emitted.
+        if self.pending.len() == start || self.discarding {
+            self.started = None;
+        } else if self.started.is_none() || start > 0 {
+            self.started = Some(received);
+        }
+        self.pending.drain(..start);
+    }
+
+    /// Emits the pending bytes as a line if no byte arrived for `gap` before `now`.
+    pub fn flush_idle(
+        &mut self,
+        gap: Duration,
+        now: DateTime<Local>,
+        emit: impl FnMut(&[u8], LineEnd),
+    ) {
+        let idle = self
+            .last_received
+            .and_then(|last_received| (now - last_received).to_std().ok())
+            .is_some_and(|elapsed| elapsed >= gap);
+        if idle {
+            self.flush(emit);
+        }
+    }
+
+    /// Emits the pending bytes as a line, when no more are coming like at the end of
+    /// a session.
+    pub fn flush(&mut self, mut emit: impl FnMut(&[u8], LineEnd)) {
+        if let (false, Some(started)) = (self.discarding, self.started) {
+            emit(&self.pending, LineEnd::Idle(started));
+        }
+        self.pending.clear();
+        self.scanned = 0;
+        self.discarding = false;
+        self.started = None;
+        self.last_received = None;
+    }
+
+    pub fn counts(&self) -> OverflowCounts {
+        self.counts
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn lines(framer: &mut LineFramer, chunks: &[&[u8]]) -> Vec<(Vec<u8>, LineEnd)> {
+        let mut lines = Vec::new();
+        for chunk in chunks {
+            framer.push(chunk, Local::now(), |line, end| {
+                lines.push((line.to_vec(), end))
+            });
+        }
+        lines
+    }
+
+    #[test]
+    fn test_split_lines() {
+        let mut framer = LineFramer::new(Delimiter::Bytes(b"\n".to_vec()), 64, Overflow::Truncate);
+        assert_eq!(
+            lines(&mut framer, &[b"one\ntw", b"o", b"\n\nthr"]),
+            vec![
+                (b"one".to_vec(), LineEnd::Delimiter),
+                (b"two".to_vec(), LineEnd::Delimiter),
+                (b"".to_vec(), LineEnd::Delimiter),
+            ]
+        );
+    }
+
+    #[test]
+    fn test_multi_byte_delimiter() {
+        let mut framer = LineFramer::new(Delimiter::Bytes(b"\r\n".to_vec()), 64, Overflow::Drop);
+        assert_eq!(
+            lines(&mut framer, &[b"one\r", b"\ntwo\rthree\r\n\r", b"\n"]),
+            vec![
+                (b"one".to_vec(), LineEnd::Delimiter),
+                (b"two\rthree".to_vec(), LineEnd::Delimiter),
+                (b"".to_vec(), LineEnd::Delimiter),
+            ]
+        );
+    }
+
+    #[test]
+    fn test_regex_delimiter() {
+        let delimiter = Delimiter::regex(r"[>#] ").unwrap();
+        let mut framer = LineFramer::new(delimiter, 64, Overflow::Truncate).keep_delimiter(true);
+        assert_eq!(
+            lines(&mut framer, &[b"login> ", b"root# ls", b"# "]),
+            vec![
+                (b"login> ".to_vec(), LineEnd::Delimiter),
+                (b"root# ".to_vec(), LineEnd::Delimiter),
+                (b"ls# ".to_vec(), LineEnd::Delimiter),
+            ]
+        );
+        assert!(Delimiter::regex("x*").is_err());
+    }
+
+    #[test]
+    fn test_idle_gap() {
+        let mut framer = LineFramer::new(Delimiter::None, 64, Overflow::Truncate);
+        let mut lines = Vec::new();
+        framer.push(b"\x01\x02", Local::now(), |line, _| {
+            lines.push(line.to_vec())
+        });
+        framer.push(b"\x03", Local::now(), |line, _| lines.push(line.to_vec()));
+        framer.flush_idle(Duration::from_secs(60), Local::now(), |line, _| {
+            lines.push(line.to_vec())
+        });
+        assert!(lines.is_empty());
+
+        framer.flush_idle(Duration::ZERO, Local::now(), |line, end| {
+            assert!(matches!(end, LineEnd::Idle(_)));
+            lines.push(line.to_vec())
+        });
+        framer.flush_idle(Duration::ZERO, Local::now(), |line, _| {
+            lines.push(line.to_vec())
+        });
+        assert_eq!(lines, vec![b"\x01\x02\x03".to_vec()]);
+    }
+
+    #[test]
+    fn test_flush() {
+        let mut framer = LineFramer::new(Delimiter::Bytes(b"\n".to_vec()), 64, Overflow::Truncate);
+        let started = Local::now();
+        framer.push(b"one\ntwo", started, |_, _| {});
+        let mut lines = Vec::new();
+        framer.flush(|line, end| lines.push((line.to_vec(), end)));
+        framer.flush(|line, end| lines.push((line.to_vec(), end)));
+        assert_eq!(lines, vec![(b"two".to_vec(), LineEnd::Idle(started))]);
+    }
+
+    #[test]
+    fn test_truncate_long_lines() {
+        let mut framer = LineFramer::new(Delimiter::Bytes(b"\n".to_vec()), 4, Overflow::Truncate);
+        assert_eq!(
+            lines(&mut framer, &[b"abc", b"defghij\nxy\n"]),
+            vec![
+                (b"abcd".to_vec(), LineEnd::Truncated),
+                (b"efgh".to_vec(), LineEnd::Truncated),
+                (b"ij".to_vec(), LineEnd::Delimiter),
+                (b"xy".to_vec(), LineEnd::Delimiter),
+            ]
+        );
+        assert_eq!(
+            framer.counts(),
+            OverflowCounts {
+                truncated: 2,
+                dropped: 0
+            }
+        );
+
+        // A line of the maximum length whose delimiter arrives in two reads fits
+        for overflow in [Overflow::Truncate, Overflow::Drop] {
+            let mut framer = LineFramer::new(Delimiter::Bytes(b"\r\n".to_vec()), 4, overflow);
+            assert_eq!(
+                lines(&mut framer, &[b"abcd\r", b"\nef\r\n"]),
+                vec![
+                    (b"abcd".to_vec(), LineEnd::Delimiter),
+                    (b"ef".to_vec(), LineEnd::Delimiter),
+                ]
+            );
+            assert_eq!(framer.counts(), OverflowCounts::default());
+        }
+    }
+
+    #[test]
+    fn test_drop_long_lines() {
+        let mut framer = LineFramer::new(Delimiter::Bytes(b"\n".to_vec()), 4, Overflow::Drop);
+        assert_eq!(
+            lines(&mut framer, &[b"abcd\nabc", b"defg", b"hij\nxy\n"]),
+            vec![
+                (b"abcd".to_vec(), LineEnd::Delimiter),
+                (b"xy".to_vec(), LineEnd::Delimiter),
+            ]
+        );
+        assert_eq!(
+            framer.counts(),
+            OverflowCounts {
+                truncated: 0,
+                dropped: 1
+            }
+        );
+    }
+}
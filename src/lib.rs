@@ -0,0 +1,12 @@
+pub mod capture;
+pub mod console;
+pub mod deferred;
+pub mod encoding;
+pub mod framing;
+pub mod mlog;
+pub mod profile;
+pub mod serial;
+pub mod slog;
+pub mod tlog;
+pub mod utils;
+pub mod writer;
@@ -1,17 +1,36 @@
-mod mlog;
-mod slog;
-mod tlog;
-mod utils;
-
-use mlog::mlog_main;
-use slog::slog_main;
-use tlog::tlog_main;
+use gesk_log::mlog::{mlog_main, MlogArgs};
+use gesk_log::profile;
+use gesk_log::slog::{slog_main, SlogArgs};
+use gesk_log::tlog::{tlog_main, TlogArgs};
 
+use clap::{Parser, Subcommand};
 use crossterm::style::Stylize;
 use enum_display_derive::{self, Display};
 use inquire::{InquireError, Select};
 use std::fmt::Display;
 
+/// Serial, TLog and MQTT logger.
+///
+/// Run without a subcommand to pick the logging mode interactively. Any value
+/// not given on the command line is asked for with a prompt.
+#[derive(Debug, Parser)]
+#[command(version, about)]
+struct Cli {
+    #[command(subcommand)]
+    mode: Option<Command>,
+}
+
+#[derive(Debug, Subcommand)]
+enum Command {
+    /// Log lines from a serial port
+    Slog(SlogArgs),
+    /// Log TLog frames from a serial port
+    Tlog(TlogArgs),
+    /// Log messages from MQTT topics
+    Mlog(MlogArgs),
+}
+
+#[allow(clippy::enum_variant_names)]
 #[derive(Debug, Display)]
 enum GeskMode {
     SLog,
@@ -21,22 +40,50 @@ enum GeskMode {
 
 #[tokio::main]
 async fn main() -> Result<(), Box<dyn std::error::Error>> {
-    let gesk_mode = loop {
-        match Select::new(
-            "Please select logging mode:",
-            vec![GeskMode::SLog, GeskMode::TLog, GeskMode::MLog],
-        )
-        .prompt()
-        {
-            Ok(mode) => break mode,
-            Err(InquireError::OperationInterrupted) => return Ok(()),
-            Err(_) => eprintln!("{}", "Please select an option.".red().slow_blink()),
+    let command = match Cli::parse().mode {
+        Some(command) => command,
+        None => {
+            let gesk_mode = loop {
+                match Select::new(
+                    "Please select logging mode:",
+                    vec![GeskMode::SLog, GeskMode::TLog, GeskMode::MLog],
+                )
+                .prompt()
+                {
+                    Ok(mode) => break mode,
+                    Err(InquireError::OperationInterrupted) => return Ok(()),
+                    Err(_) => eprintln!("{}", "Please select an option.".red().slow_blink()),
+                }
+            };
+
+            let mode_name = gesk_mode.to_string().to_lowercase();
+            let Some(profile) = profile::select(&mode_name) else {
+                return Ok(());
+            };
+
+            match gesk_mode {
+                GeskMode::SLog => {
+                    let mut args = SlogArgs::default();
+                    args.profile = profile;
+                    Command::Slog(args)
+                }
+                GeskMode::TLog => {
+                    let mut args = TlogArgs::default();
+                    args.profile = profile;
+                    Command::Tlog(args)
+                }
+                GeskMode::MLog => {
+                    let mut args = MlogArgs::default();
+                    args.profile = profile;
+                    Command::Mlog(args)
+                }
+            }
         }
     };
 
-    match gesk_mode {
-        GeskMode::SLog => slog_main(true),
-        GeskMode::TLog => tlog_main(true),
-        GeskMode::MLog => Ok(mlog_main().await?),
+    match command {
+        Command::Slog(args) => slog_main(args),
+        Command::Tlog(args) => tlog_main(args),
+        Command::Mlog(args) => Ok(mlog_main(args).await?),
     }
 }
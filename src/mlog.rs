@@ -1,3 +1,4 @@
+use clap::{error::ErrorKind, Args as _};
 use crossterm::style::Stylize;
 use inquire::{
     validator::Validation, Confirm, CustomType, InquireError, Password, PasswordDisplayMode,
@@ -9,16 +10,23 @@ use rumqttc::{
 use serde::{Deserialize, Serialize};
 use std::{
     collections::HashMap,
+    fmt::Display,
     fs::{create_dir_all, File, OpenOptions},
     io::{self, Read, Write},
-    path::Path,
+    path::{Path, PathBuf},
     time::Duration,
 };
 
 use serde_json::value::RawValue;
 
+use crate::profile;
 use crate::utils::generate_timestamp;
 
+/// Profile a run without `--profile` or `--config` takes its values from.
+const DEFAULT_PROFILE: &str = "default";
+/// Config file of runs from before profiles, moved into [`DEFAULT_PROFILE`] once.
+const LEGACY_CONFIG: &str = "mlog_config.json";
+
 #[derive(Debug, Serialize, Deserialize)]
 struct PartialArgsFromFile {
     /// Domain name or IP address of the broker
@@ -47,12 +55,12 @@ struct PartialArgsFromFile {
 }
 
 impl PartialArgsFromFile {
-    fn new() -> Option<Self> {
-        let file = File::open("mlog_config.json").ok();
+    fn new(path: &Path) -> Option<Self> {
+        let file = File::open(path).ok();
         let mut buffer = Vec::new();
 
         if let Some(mut file) = file {
-            let __ = file.read_to_end(&mut buffer);
+            let _ = file.read_to_end(&mut buffer);
 
             return serde_json::from_slice(&buffer).ok();
         }
@@ -62,6 +70,58 @@ impl PartialArgsFromFile {
 }
 
 /// MQTT Logger
+#[derive(Debug, Clone, Default, clap::Args)]
+pub struct MlogArgs {
+    /// Saved profile to take the values not given on the command line from
+    #[arg(short = 'P', long)]
+    pub profile: Option<String>,
+
+    /// Config file the values are loaded from instead of a profile [default: the profile
+    /// named default]
+    #[arg(short, long, conflicts_with = "profile")]
+    config: Option<PathBuf>,
+
+    /// Domain name or IP address of the broker
+    #[arg(short, long)]
+    broker: Option<String>,
+
+    /// Port on which the broker is expected to listen for incoming connections
+    #[arg(short, long)]
+    port: Option<u16>,
+
+    /// Topic to be monitored, can be given multiple times
+    #[arg(short, long = "topic")]
+    topics: Vec<String>,
+
+    /// Identifier for the device connecting to the broker, `random` for a generated one
+    #[arg(short, long)]
+    id: Option<String>,
+
+    /// Duration in seconds to wait before pinging the broker if there's no other communication
+    #[arg(short, long)]
+    keep_alive: Option<u64>,
+
+    /// Number of concurrent in flight messages
+    #[arg(long)]
+    inflight: Option<u16>,
+
+    /// Username for logging in to the broker
+    #[arg(short, long, requires = "password")]
+    username: Option<String>,
+
+    /// Password for logging in to the broker
+    #[arg(long, requires = "username")]
+    password: Option<String>,
+
+    /// Clean Session
+    #[arg(long)]
+    clean_session: Option<bool>,
+
+    /// Never prompt, use defaults for missing values and fail if a required one is missing
+    #[arg(long)]
+    non_interactive: bool,
+}
+
 #[derive(Debug, Serialize, Deserialize)]
 struct Args {
     /// Domain name or IP address of the broker
@@ -97,74 +157,102 @@ fn clean_str(s: &str) -> String {
 }
 
 impl Args {
-    fn parse() -> Self {
-        let args_from_file = PartialArgsFromFile::new();
+    fn parse(cli: MlogArgs) -> Result<Self, clap::Error> {
+        let args_from_file = match (&cli.profile, &cli.config) {
+            (Some(name), _) => match profile::load::<PartialArgsFromFile>("mlog", name) {
+                Ok(args) => Some(args),
+                Err(e) => return Err(arg_error(ErrorKind::InvalidValue, e)),
+            },
+            (None, Some(config)) => PartialArgsFromFile::new(config),
+            (None, None) => default_profile(),
+        };
+        let non_interactive = cli.non_interactive;
 
         let mut amount_of_changes = 0;
 
-        let broker = match args_from_file.as_ref().map(|a| clean_str(a.broker.get())) {
+        let broker = match cli
+            .broker
+            .or_else(|| args_from_file.as_ref().map(|a| clean_str(a.broker.get())))
+        {
             Some(val) if !val.is_empty() => val,
+            _ if non_interactive => return Err(missing_arg("--broker")),
             _ => {
                 amount_of_changes += 1;
                 get_broker()
             }
         };
 
-        let port = match args_from_file
-            .as_ref()
-            .map(|a| a.port.get())
-            .and_then(|s| s.parse::<u16>().ok())
-        {
+        let port = match cli.port.or_else(|| {
+            args_from_file
+                .as_ref()
+                .map(|a| a.port.get())
+                .and_then(|s| s.parse::<u16>().ok())
+        }) {
             Some(val) => val,
+            _ if non_interactive => return Err(missing_arg("--port")),
             _ => {
                 amount_of_changes += 1;
                 get_port()
             }
         };
 
-        let id = match args_from_file.as_ref().map(|a| clean_str(a.id.get())) {
+        let id = match cli
+            .id
+            .or_else(|| args_from_file.as_ref().map(|a| clean_str(a.id.get())))
+        {
             Some(val) if !val.is_empty() => val.to_string(),
+            _ if non_interactive => "random".to_string(),
             _ => {
                 amount_of_changes += 1;
                 get_id()
             }
         };
 
-        let topics = match args_from_file
-            .as_ref()
-            .map(|a| a.topics.get())
-            .and_then(|s| serde_json::from_str::<Vec<String>>(s).ok())
-        {
+        let topics = match Some(cli.topics)
+            .filter(|topics| !topics.is_empty())
+            .or_else(|| {
+                args_from_file
+                    .as_ref()
+                    .map(|a| a.topics.get())
+                    .and_then(|s| serde_json::from_str::<Vec<String>>(s).ok())
+            }) {
             Some(val) if !val.is_empty() => val,
+            _ if non_interactive => return Err(missing_arg("--topic")),
             _ => {
                 amount_of_changes += 1;
                 get_topics()
             }
         };
 
-        let keep_alive = match args_from_file
-            .as_ref()
-            .map(|a| a.keep_alive.get())
-            .and_then(|s| s.parse::<u64>().ok())
-        {
+        let keep_alive = match cli.keep_alive.or_else(|| {
+            args_from_file
+                .as_ref()
+                .map(|a| a.keep_alive.get())
+                .and_then(|s| s.parse::<u64>().ok())
+        }) {
             Some(val) => val,
+            _ if non_interactive => 5,
             _ => {
                 amount_of_changes += 1;
                 get_keep_alieve()
             }
         };
 
-        let inflight = args_from_file
-            .as_ref()
-            .and_then(|a| {
-                let str_val = a.inflight.get();
-                if str_val == "null" {
-                    return None;
-                }
-                str_val.parse::<u16>().ok()
+        let inflight = cli
+            .inflight
+            .or_else(|| {
+                args_from_file.as_ref().and_then(|a| {
+                    let str_val = a.inflight.get();
+                    if str_val == "null" {
+                        return None;
+                    }
+                    str_val.parse::<u16>().ok()
+                })
             })
             .or_else(|| {
-                if args_from_file.as_ref().map(|a| a.inflight.get()) == Some("null") {
+                if non_interactive
+                    || args_from_file.as_ref().map(|a| a.inflight.get()) == Some("null")
+                {
                     None
                 } else {
                     {
@@ -174,24 +262,32 @@ impl Args {
                 }
             });
 
-        let auth = match args_from_file
-            .as_ref()
-            .map(|a| a.auth.get())
-            .and_then(|s| serde_json::from_str::<Vec<String>>(s).ok())
-        {
+        let auth = match cli
+            .username
+            .zip(cli.password)
+            .map(|(username, password)| vec![username, password])
+            .or_else(|| {
+                args_from_file
+                    .as_ref()
+                    .map(|a| a.auth.get())
+                    .and_then(|s| serde_json::from_str::<Vec<String>>(s).ok())
+            }) {
             Some(val) => val,
+            _ if non_interactive => vec![],
             _ => {
                 amount_of_changes += 1;
                 get_credentials()
             }
         };
 
-        let clean_session = match args_from_file
-            .as_ref()
-            .map(|a| a.clean_session.get())
-            .and_then(|s| s.parse::<bool>().ok())
-        {
+        let clean_session = match cli.clean_session.or_else(|| {
+            args_from_file
+                .as_ref()
+                .map(|a| a.clean_session.get())
+                .and_then(|s| s.parse::<bool>().ok())
+        }) {
             Some(val) => val,
+            _ if non_interactive => false,
             _ => {
                 amount_of_changes += 1;
                 get_clean_session()
@@ -209,10 +305,9 @@ impl Args {
             clean_session,
         };
 
-        if let Ok(json_ver) = serde_json::to_string_pretty(&res) {
-            if amount_of_changes != 0 {
-                save_to_json(json_ver);
-            }
+        if amount_of_changes != 0 {
+            let current = cli.profile.as_deref().or(Some(DEFAULT_PROFILE));
+            profile::offer_save("mlog", &res, current);
         }
 
         if res.id == "random" {
@@ -221,41 +316,43 @@ impl Args {
 
         println!("Loaded State: \n{:#?}", &res);
 
-        res
+        Ok(res)
     }
 }
 
-fn save_to_json(json_ver: String) {
-    let write = loop {
-        match Confirm::new("Do you want to save this as a config file?")
-            .with_default(true)
-            .prompt()
-        {
-            Ok(ans) => break ans,
-            Err(InquireError::OperationInterrupted) => ::std::process::exit(1),
-            Err(_) => eprintln!("{}", "Please type a correct value".red().slow_blink()),
-        }
-    };
-
-    if write {
-        let file = match OpenOptions::new()
-            .truncate(true)
-            .write(true)
-            .create(true)
-            .open("mlog_config.json")
-        {
-            Ok(f) => Some(f),
+/// The [`DEFAULT_PROFILE`], made from [`LEGACY_CONFIG`] if there is none yet.
+fn default_profile() -> Option<PartialArgsFromFile> {
+    if profile::names("mlog")
+        .iter()
+        .any(|name| name == DEFAULT_PROFILE)
+    {
+        return match profile::load("mlog", DEFAULT_PROFILE) {
+            Ok(args) => Some(args),
             Err(e) => {
-                eprintln!("Failed to create/open file for config {e}");
+                eprintln!("{e}");
                 None
             }
         };
+    }
 
-        if let Some(mut file) = file {
-            file.write_all(json_ver.as_bytes()).unwrap();
-            file.flush().unwrap();
-        }
+    let args = PartialArgsFromFile::new(Path::new(LEGACY_CONFIG))?;
+    match profile::save("mlog", DEFAULT_PROFILE, &args) {
+        Ok(()) => println!("Saved \"{LEGACY_CONFIG}\" as the \"{DEFAULT_PROFILE}\" mlog profile"),
+        Err(e) => eprintln!("{e}"),
     }
+    Some(args)
+}
+
+/// An error about the mlog arguments, shown with their usage.
+fn arg_error(kind: ErrorKind, message: impl Display) -> clap::Error {
+    MlogArgs::augment_args(clap::Command::new("gesk-log mlog")).error(kind, message)
+}
+
+fn missing_arg(arg: &str) -> clap::Error {
+    arg_error(
+        ErrorKind::MissingRequiredArgument,
+        format!("{arg} is required with --non-interactive"),
+    )
 }
 
 fn get_clean_session() -> bool {
@@ -423,8 +520,8 @@ fn get_credentials() -> Vec<String> {
     credentials
 }
 
-pub async fn mlog_main() -> std::io::Result<()> {
-    let args = Args::parse();
+pub async fn mlog_main(cli: MlogArgs) -> std::io::Result<()> {
+    let args = Args::parse(cli).unwrap_or_else(|e| e.exit());
 
     let mqttoptions = configure_mqtt(&args);
 
@@ -514,7 +611,7 @@ async fn process_events(
     Ok(())
 }
 
-fn write_to_file(timestamp: &Vec<u8>, data: &Publish, files: &HashMap<String, File>) {
+fn write_to_file(timestamp: &[u8], data: &Publish, files: &HashMap<String, File>) {
     let mut res = Vec::with_capacity(data.payload.len() + timestamp.len());
 
     res.extend_from_slice(timestamp);
@@ -533,7 +630,7 @@ fn write_to_file(timestamp: &Vec<u8>, data: &Publish, files: &HashMap<String, Fi
     };
 }
 
-fn write_to_stdout(timestamp: &Vec<u8>, data: &Publish) {
+fn write_to_stdout(timestamp: &[u8], data: &Publish) {
     let mut res = Vec::with_capacity(data.payload.len() + timestamp.len());
 
     res.extend_from_slice(timestamp);
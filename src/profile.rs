@@ -0,0 +1,158 @@
+use crossterm::style::Stylize;
+use inquire::{validator::Validation, Confirm, InquireError, Select, Text};
+use serde::{de::DeserializeOwned, Serialize};
+use serde_json::Value;
+use std::{
+    collections::BTreeMap,
+    fs::{self, OpenOptions},
+    io::{ErrorKind, Write},
+};
+
+/// File holding the saved profiles of every mode.
+const PROFILES_FILE: &str = "gesk_profiles.json";
+
+/// Named profiles grouped by mode, e.g. `{"slog": {"board": {...}}, "mlog": {...}}`.
+type Profiles = BTreeMap<String, BTreeMap<String, Value>>;
+
+/// The saved profiles, none if the file doesn't exist yet.
+fn read_profiles() -> Result<Profiles, String> {
+    match fs::read(PROFILES_FILE) {
+        Ok(buffer) => serde_json::from_slice(&buffer)
+            .map_err(|e| format!("Failed to parse \"{PROFILES_FILE}\". Error: {e}")),
+        Err(e) if e.kind() == ErrorKind::NotFound => Ok(Profiles::new()),
+        Err(e) => Err(format!("Failed to read \"{PROFILES_FILE}\". Error: {e}")),
+    }
+}
+
+/// Names of the saved profiles of `mode`, none if they can't be read.
+pub fn names(mode: &str) -> Vec<String> {
+    match read_profiles() {
+        Ok(mut profiles) => profiles
+            .remove(mode)
+            .map(|profiles| profiles.into_keys().collect())
+            .unwrap_or_default(),
+        Err(e) => {
+            eprintln!("{e}");
+            Vec::new()
+        }
+    }
+}
+
+/// Loads the profile `name` of `mode`.
+pub fn load<T: DeserializeOwned>(mode: &str, name: &str) -> Result<T, String> {
+    let profile = read_profiles()?
+        .remove(mode)
+        .and_then(|mut profiles| profiles.remove(name))
+        .ok_or(format!(
+            "No {mode} profile named \"{name}\" in \"{PROFILES_FILE}\""
+        ))?;
+
+    // Round trip through a string so types borrowing from the input, like `RawValue`, work too.
+    serde_json::from_str(&profile.to_string())
+        .map_err(|e| format!("Invalid {mode} profile \"{name}\". Error: {e}"))
+}
+
+/// Asks which saved profile of `mode` to start from. Returns `Some(None)` for a new
+/// session or when there are no profiles, and `None` if the user interrupts.
+pub fn select(mode: &str) -> Option<Option<String>> {
+    let names = names(mode);
+    if names.is_empty() {
+        return Some(None);
+    }
+
+    loop {
+        match Select::new("Select a profile:", names.clone())
+            .with_help_message("esc for a new session")
+            .prompt_skippable()
+        {
+            Ok(ans) => break Some(ans),
+            Err(InquireError::OperationInterrupted) => break None,
+            Err(_) => eprintln!("{}", "Please select an option.".red().slow_blink()),
+        }
+    }
+}
+
+/// Offers to save `profile` under a name of the user's choice, `current` being the
+/// profile the session was started from, if any. Nothing is offered if the saved
+/// profiles can't be read, so they aren't written over.
+pub fn offer_save<T: Serialize>(mode: &str, profile: &T, current: Option<&str>) {
+    let profiles = match read_profiles() {
+        Ok(profiles) => profiles,
+        Err(e) => {
+            eprintln!("{e}. Not saving the profile over it.");
+            return;
+        }
+    };
+
+    let write = loop {
+        match Confirm::new("Do you want to save this as a profile?")
+            .with_default(true)
+            .prompt()
+        {
+            Ok(ans) => break ans,
+            Err(InquireError::OperationInterrupted) => return,
+            Err(_) => eprintln!("{}", "Please type a correct value".red().slow_blink()),
+        }
+    };
+
+    if !write {
+        return;
+    }
+
+    let name = loop {
+        let mut prompt = Text::new("Profile name:").with_validator(|input: &str| {
+            if input.trim().is_empty() {
+                Ok(Validation::Invalid("Name must not be empty".into()))
+            } else {
+                Ok(Validation::Valid)
+            }
+        });
+        if let Some(current) = current {
+            prompt = prompt.with_initial_value(current);
+        }
+
+        match prompt.prompt() {
+            Ok(ans) => break ans,
+            Err(InquireError::OperationInterrupted) => return,
+            Err(_) => eprintln!("{}", "Please type a correct value".red().slow_blink()),
+        }
+    };
+
+    if let Err(e) = insert(profiles, mode, name.trim(), profile) {
+        eprintln!("{e}");
+    }
+}
+
+/// Saves `profile` as the profile `name` of `mode`, next to the other saved profiles.
+pub fn save<T: Serialize>(mode: &str, name: &str, profile: &T) -> Result<(), String> {
+    insert(read_profiles()?, mode, name, profile)
+}
+
+/// Writes `profiles` with `profile` added as the profile `name` of `mode`.
+fn insert<T: Serialize>(
+    mut profiles: Profiles,
+    mode: &str,
+    name: &str,
+    profile: &T,
+) -> Result<(), String> {
+    let profile =
+        serde_json::to_value(profile).map_err(|e| format!("Failed to serialize profile {e}"))?;
+
+    profiles
+        .entry(mode.to_owned())
+        .or_default()
+        .insert(name.to_owned(), profile);
+
+    let json_ver = serde_json::to_string_pretty(&profiles)
+        .map_err(|e| format!("Failed to serialize profiles {e}"))?;
+
+    let mut file = OpenOptions::new()
+        .truncate(true)
+        .write(true)
+        .create(true)
+        .open(PROFILES_FILE)
+        .map_err(|e| format!("Failed to create/open file for profiles {e}"))?;
+    file.write_all(json_ver.as_bytes())
+        .and_then(|_| file.flush())
+        .map_err(|e| format!("Failed to write \"{PROFILES_FILE}\". Error: {e}"))
+}
@@ -0,0 +1,893 @@
+use chrono::{DateTime, Local};
+use clap::{builder::BoolishValueParser, ValueEnum};
+use crossterm::style::Stylize;
+use inquire::{validator::Validation, InquireError, Select, Text};
+use serde::{Deserialize, Serialize};
+use serialport::{available_ports, SerialPort, SerialPortBuilder, SerialPortInfo, SerialPortType};
+use std::fmt::Display;
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::str::FromStr;
+use std::sync::mpsc::Receiver;
+use std::thread;
+use std::time::Duration;
+
+use crate::console::ConsoleCommand;
+use crate::utils::format_timestamp;
+
+/// Size of the buffer the port is read into, enough for several milliseconds at multi-megabaud rates.
+pub const READ_BUFFER_SIZE: usize = 64 * 1024;
+
+/// Path of the device node of `info`.
+fn port_path(info: &SerialPortInfo) -> String {
+    if info.port_name.starts_with("/sys/class/tty/") {
+        info.port_name.replace("/sys/class/tty/", "/dev/")
+    } else {
+        info.port_name.clone()
+    }
+}
+
+/// An entry of the port picker, showing what is known about the device.
+enum PortChoice {
+    Port(SerialPortInfo),
+    /// Waits until a port is detected, when there are none yet.
+    Wait,
+    /// Lets the user type a path, for ports that are not detected such as PTYs.
+    Manual,
+}
+
+impl Display for PortChoice {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let info = match self {
+            Self::Port(info) => info,
+            Self::Wait => return write!(f, "Wait for a serial interface"),
+            Self::Manual => return write!(f, "Enter a path manually"),
+        };
+
+        write!(f, "{}", port_path(info))?;
+        if let SerialPortType::UsbPort(usb) = &info.port_type {
+            let name = [usb.manufacturer.as_deref(), usb.product.as_deref()]
+                .into_iter()
+                .flatten()
+                .collect::<Vec<_>>()
+                .join(" ");
+            if !name.is_empty() {
+                write!(f, " - {name}")?;
+            }
+            write!(f, " [{:04x}:{:04x}", usb.vid, usb.pid)?;
+            if let Some(serial_number) = &usb.serial_number {
+                write!(f, " SN {serial_number}")?;
+            }
+            write!(f, "]")?;
+        }
+        Ok(())
+    }
+}
+
+/// Asks the user to pick one of the available serial ports or to type a path.
+/// Returns the selector of the picked port, or `None` if the user interrupts.
+pub fn select_port() -> Option<String> {
+    let mut wait = false;
+    loop {
+        let options = match available_ports() {
+            Ok(options) => options,
+            Err(e) => {
+                eprintln!("Failed to detect ports, type the path instead. Error: {e}");
+                return get_port_path().flatten();
+            }
+        };
+        if options.is_empty() && wait {
+            thread::sleep(Duration::from_millis(100));
+            continue;
+        }
+
+        let choices = if options.is_empty() {
+            eprintln!("No serial interfaces found.");
+            vec![PortChoice::Wait, PortChoice::Manual]
+        } else {
+            options
+                .into_iter()
+                .map(PortChoice::Port)
+                .chain([PortChoice::Manual])
+                .collect()
+        };
+
+        match Select::new("Select the port to read from:", choices).prompt() {
+            Ok(PortChoice::Port(info)) => return Some(PortSelector::of(&info).to_string()),
+            Ok(PortChoice::Wait) => {
+                eprintln!("Waiting for serial interfaces...");
+                wait = true;
+            }
+            Ok(PortChoice::Manual) => match get_port_path() {
+                Some(Some(path)) => return Some(path),
+                Some(None) => wait = false, // Back to the list.
+                None => return None,
+            },
+            Err(InquireError::OperationInterrupted) => return None,
+            Err(_) => wait = false, // Restarts to check for more iterfaces.
+        }
+    }
+}
+
+/// Asks for the path of a port, `Some(None)` when the user goes back and `None`
+/// if the user interrupts.
+fn get_port_path() -> Option<Option<String>> {
+    loop {
+        match Text::new("Enter the path of the port:")
+            .with_help_message("e.g. /dev/pts/3, COM3 or usb:VID:PID[:SERIAL], esc to go back")
+            .with_validator(|input: &str| {
+                if input.trim().is_empty() {
+                    Ok(Validation::Invalid("Path must not be empty".into()))
+                } else {
+                    Ok(Validation::Valid)
+                }
+            })
+            .prompt_skippable()
+        {
+            Ok(ans) => break Some(ans.map(|path| path.trim().to_owned())),
+            Err(InquireError::OperationInterrupted) => break None,
+            Err(_) => eprintln!("{}", "Please type a correct value".red().slow_blink()),
+        }
+    }
+}
+
+/// Which device to open, given as a path or as `usb:VID:PID[:SERIAL]` with the
+/// IDs in hex, the latter staying the same when device numbering changes.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum PortSelector {
+    /// USB devices are matched by VID, PID and, if given, serial number.
+    Usb {
+        vid: u16,
+        pid: u16,
+        serial_number: Option<String>,
+    },
+    /// Anything else, including `/dev/serial/by-id/` links, is opened by path.
+    Path(String),
+}
+
+impl PortSelector {
+    /// The most stable selector for `info`, USB devices with a serial number are
+    /// selected by their IDs and everything else by path.
+    fn of(info: &SerialPortInfo) -> Self {
+        match &info.port_type {
+            SerialPortType::UsbPort(usb) if usb.serial_number.is_some() => Self::Usb {
+                vid: usb.vid,
+                pid: usb.pid,
+                serial_number: usb.serial_number.clone(),
+            },
+            _ => Self::Path(port_path(info)),
+        }
+    }
+
+    fn matches(&self, info: &SerialPortInfo) -> bool {
+        match (self, &info.port_type) {
+            (
+                Self::Usb {
+                    vid,
+                    pid,
+                    serial_number,
+                },
+                SerialPortType::UsbPort(usb),
+            ) => {
+                usb.vid == *vid
+                    && usb.pid == *pid
+                    && (serial_number.is_none() || &usb.serial_number == serial_number)
+            }
+            _ => false,
+        }
+    }
+
+    /// Current path of the selected device, if it is connected.
+    pub fn find(&self) -> Option<String> {
+        match self {
+            Self::Usb { .. } => available_ports()
+                .unwrap_or_default()
+                .iter()
+                .find(|info| self.matches(info))
+                .map(port_path),
+            // Virtual ports are often not listed at all and names like `COM3` are
+            // not paths on disk, so either is enough.
+            Self::Path(path) => (Path::new(path).exists()
+                || available_ports()
+                    .unwrap_or_default()
+                    .iter()
+                    .any(|info| &port_path(info) == path))
+            .then(|| path.clone()),
+        }
+    }
+}
+
+impl FromStr for PortSelector {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let Some(ids) = s.strip_prefix("usb:") else {
+            return Ok(Self::Path(s.to_owned()));
+        };
+
+        let mut parts = ids.splitn(3, ':');
+        let mut parse_id = |name| {
+            parts
+                .next()
+                .and_then(|id| u16::from_str_radix(id, 16).ok())
+                .ok_or(format!(
+                    "Invalid {name} in port \"{s}\", expected usb:VID:PID[:SERIAL]"
+                ))
+        };
+        let vid = parse_id("VID")?;
+        let pid = parse_id("PID")?;
+        let serial_number = parts.next().filter(|sn| !sn.is_empty()).map(str::to_owned);
+
+        Ok(Self::Usb {
+            vid,
+            pid,
+            serial_number,
+        })
+    }
+}
+
+impl Display for PortSelector {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::Usb {
+                vid,
+                pid,
+                serial_number: Some(serial_number),
+            } => write!(f, "usb:{vid:04x}:{pid:04x}:{serial_number}"),
+            Self::Usb { vid, pid, .. } => write!(f, "usb:{vid:04x}:{pid:04x}"),
+            Self::Path(path) => write!(f, "{path}"),
+        }
+    }
+}
+
+/// Identity of an opened port, used to find the same device again after it
+/// disconnects and re-enumerates.
+#[derive(Debug, Clone)]
+pub struct PortIdentity {
+    selector: PortSelector,
+}
+
+impl PortIdentity {
+    /// Looks up the identity of the device currently at `path`, resolving links
+    /// like `/dev/serial/by-id/...` to the device they point to.
+    pub fn of(path: &str) -> Self {
+        let device = fs::canonicalize(path).unwrap_or_else(|_| PathBuf::from(path));
+        let info = available_ports()
+            .unwrap_or_default()
+            .into_iter()
+            .find(|info| Path::new(&port_path(info)) == device);
+
+        Self {
+            selector: Self::selector(path, info.as_ref()),
+        }
+    }
+
+    /// Selects USB devices with a serial number by their IDs. Anything else is
+    /// selected by the `path` it was opened with, as the IDs of an adapter without a
+    /// serial number also match any identical adapter.
+    fn selector(path: &str, info: Option<&SerialPortInfo>) -> PortSelector {
+        match info.map(PortSelector::of) {
+            Some(usb @ PortSelector::Usb { .. }) => usb,
+            _ => PortSelector::Path(path.to_owned()),
+        }
+    }
+
+    /// Blocks until the device is back and could be opened with `builder`.
+    /// Returns the port along with the path it was found at, or `None` if
+    /// [`ConsoleCommand::Quit`] comes in while waiting.
+    pub fn reconnect(
+        &self,
+        builder: &SerialPortBuilder,
+        commands: &Receiver<ConsoleCommand>,
+    ) -> Option<(String, Box<dyn SerialPort>)> {
+        loop {
+            if let Some(path) = self.selector.find() {
+                if let Ok(port) = builder.clone().path(&path).open() {
+                    return Some((path, port));
+                }
+            }
+
+            while let Ok(command) = commands.try_recv() {
+                match command {
+                    ConsoleCommand::Quit => return None,
+                    _ => eprintln!("The port is disconnected, waiting for it to come back"),
+                }
+            }
+            thread::sleep(Duration::from_millis(100));
+        }
+    }
+}
+
+/// Resolves a port selector given by the user to the path of the device.
+pub fn resolve_port(port: &str) -> Result<String, String> {
+    let selector = port.parse::<PortSelector>()?;
+    selector
+        .find()
+        .ok_or(format!("No serial port matching \"{selector}\" found"))
+}
+
+/// Parity bit of a serial frame.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, ValueEnum, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Parity {
+    #[default]
+    None,
+    Odd,
+    Even,
+}
+
+/// Flow control of a serial port.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, ValueEnum, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum FlowControl {
+    #[default]
+    None,
+    /// XON/XOFF
+    Software,
+    /// RTS/CTS
+    Hardware,
+}
+
+impl Display for FlowControl {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::None => write!(f, "None"),
+            Self::Software => write!(f, "Software (XON/XOFF)"),
+            Self::Hardware => write!(f, "Hardware (RTS/CTS)"),
+        }
+    }
+}
+
+/// Line settings of a port besides the baud rate, as given on the command line
+/// or in a profile.
+#[derive(Debug, Clone, Default, clap::Args, Serialize, Deserialize)]
+#[serde(default)]
+pub struct LineArgs {
+    /// Number of data bits [default: 8]
+    #[arg(long, value_parser = clap::value_parser!(u8).range(5..=8))]
+    #[serde(skip_serializing_if = "Option::is_none")]
+    data_bits: Option<u8>,
+
+    /// Parity bit [default: none]
+    #[arg(long)]
+    #[serde(skip_serializing_if = "Option::is_none")]
+    parity: Option<Parity>,
+
+    /// Number of stop bits [default: 1]
+    #[arg(long, value_parser = clap::value_parser!(u8).range(1..=2))]
+    #[serde(skip_serializing_if = "Option::is_none")]
+    stop_bits: Option<u8>,
+
+    /// Flow control [default: none]
+    #[arg(long)]
+    #[serde(skip_serializing_if = "Option::is_none")]
+    flow_control: Option<FlowControl>,
+}
+
+impl LineArgs {
+    /// Fills the values not given on the command line from `profile`.
+    pub fn merge(self, profile: Self) -> Self {
+        Self {
+            data_bits: self.data_bits.or(profile.data_bits),
+            parity: self.parity.or(profile.parity),
+            stop_bits: self.stop_bits.or(profile.stop_bits),
+            flow_control: self.flow_control.or(profile.flow_control),
+        }
+    }
+
+    /// Fills in the missing settings, asking for them unless `non_interactive`.
+    /// Sets `prompted` if anything was asked and returns `None` if the user interrupts.
+    pub fn resolve(&self, non_interactive: bool, prompted: &mut bool) -> Option<LineSettings> {
+        let given = self.data_bits.is_some() || self.parity.is_some() || self.stop_bits.is_some();
+        let mut settings = if given || non_interactive {
+            LineSettings::default()
+        } else {
+            *prompted = true;
+            get_frame_format()?
+        };
+
+        settings.data_bits = self.data_bits.unwrap_or(settings.data_bits);
+        settings.parity = self.parity.unwrap_or(settings.parity);
+        settings.stop_bits = self.stop_bits.unwrap_or(settings.stop_bits);
+        settings.flow_control = match self.flow_control {
+            Some(flow_control) => flow_control,
+            None if non_interactive => FlowControl::None,
+            None => {
+                *prompted = true;
+                get_flow_control()?
+            }
+        };
+
+        Some(settings)
+    }
+}
+
+/// Line settings of a port besides the baud rate.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct LineSettings {
+    data_bits: u8,
+    parity: Parity,
+    stop_bits: u8,
+    flow_control: FlowControl,
+}
+
+impl Default for LineSettings {
+    fn default() -> Self {
+        Self {
+            data_bits: 8,
+            parity: Parity::None,
+            stop_bits: 1,
+            flow_control: FlowControl::None,
+        }
+    }
+}
+
+impl LineSettings {
+    /// Configures `builder` to open ports with these settings.
+    pub fn apply(&self, builder: SerialPortBuilder) -> SerialPortBuilder {
+        let data_bits = match self.data_bits {
+            5 => serialport::DataBits::Five,
+            6 => serialport::DataBits::Six,
+            7 => serialport::DataBits::Seven,
+            _ => serialport::DataBits::Eight,
+        };
+        let parity = match self.parity {
+            Parity::None => serialport::Parity::None,
+            Parity::Odd => serialport::Parity::Odd,
+            Parity::Even => serialport::Parity::Even,
+        };
+        let stop_bits = match self.stop_bits {
+            2 => serialport::StopBits::Two,
+            _ => serialport::StopBits::One,
+        };
+        let flow_control = match self.flow_control {
+            FlowControl::None => serialport::FlowControl::None,
+            FlowControl::Software => serialport::FlowControl::Software,
+            FlowControl::Hardware => serialport::FlowControl::Hardware,
+        };
+
+        builder
+            .data_bits(data_bits)
+            .parity(parity)
+            .stop_bits(stop_bits)
+            .flow_control(flow_control)
+    }
+
+    /// The settings as they are saved in a profile.
+    pub fn to_args(self) -> LineArgs {
+        LineArgs {
+            data_bits: Some(self.data_bits),
+            parity: Some(self.parity),
+            stop_bits: Some(self.stop_bits),
+            flow_control: Some(self.flow_control),
+        }
+    }
+}
+
+/// Shown as e.g. `8N1` or `7E1, Hardware (RTS/CTS) flow control`.
+impl Display for LineSettings {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let parity = match self.parity {
+            Parity::None => 'N',
+            Parity::Odd => 'O',
+            Parity::Even => 'E',
+        };
+        write!(f, "{}{}{}", self.data_bits, parity, self.stop_bits)?;
+        if self.flow_control != FlowControl::None {
+            write!(f, ", {} flow control", self.flow_control)?;
+        }
+        Ok(())
+    }
+}
+
+impl FromStr for LineSettings {
+    type Err = String;
+
+    /// Parses a frame format such as `8N1`, leaving flow control off.
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let invalid = || format!("Invalid frame format \"{s}\", expected e.g. 8N1 or 7E1");
+        let [data_bits, parity, stop_bits] = s.trim().as_bytes() else {
+            return Err(invalid());
+        };
+
+        let parity = match parity.to_ascii_uppercase() {
+            b'N' => Parity::None,
+            b'O' => Parity::Odd,
+            b'E' => Parity::Even,
+            _ => return Err(invalid()),
+        };
+        if !(b'5'..=b'8').contains(data_bits) || !(b'1'..=b'2').contains(stop_bits) {
+            return Err(invalid());
+        }
+
+        Ok(Self {
+            data_bits: data_bits - b'0',
+            parity,
+            stop_bits: stop_bits - b'0',
+            flow_control: FlowControl::None,
+        })
+    }
+}
+
+fn get_frame_format() -> Option<LineSettings> {
+    loop {
+        match Text::new("What is the frame format?:")
+            .with_help_message("data bits, parity and stop bits like 8N1 or 7E1, esc for default")
+            .with_validator(|input: &str| {
+                Ok(match input.parse::<LineSettings>() {
+                    Ok(_) => Validation::Valid,
+                    Err(e) => Validation::Invalid(e.into()),
+                })
+            })
+            .prompt_skippable()
+        {
+            Ok(ans) => break Some(ans.map_or(LineSettings::default(), |ans| ans.parse().unwrap())),
+            Err(InquireError::OperationInterrupted) => break None,
+            Err(_) => eprintln!("{}", "Please type a correct value".red().slow_blink()),
+        }
+    }
+}
+
+fn get_flow_control() -> Option<FlowControl> {
+    loop {
+        match Select::new(
+            "Select the flow control:",
+            vec![
+                FlowControl::None,
+                FlowControl::Software,
+                FlowControl::Hardware,
+            ],
+        )
+        .with_help_message("esc for default")
+        .prompt_skippable()
+        {
+            Ok(ans) => break Some(ans.unwrap_or_default()),
+            Err(InquireError::OperationInterrupted) => break None,
+            Err(_) => eprintln!("{}", "Please select an option.".red().slow_blink()),
+        }
+    }
+}
+
+/// Reset sequences available by name, written in the same syntax as custom ones.
+const RESET_SEQUENCES: &[(&str, &str)] = &[
+    // EN is pulled low by RTS on the usual ESP32 auto-reset circuit.
+    ("esp32", "dtr=0,rts=1,wait=100,rts=0"),
+    // GPIO0 is pulled low by DTR while EN is released.
+    (
+        "esp32-bootloader",
+        "dtr=0,rts=1,wait=100,dtr=1,rts=0,wait=50,dtr=0",
+    ),
+    ("dtr-pulse", "dtr=1,wait=100,dtr=0"),
+    ("rts-pulse", "rts=1,wait=100,rts=0"),
+];
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum ResetStep {
+    Dtr(bool),
+    Rts(bool),
+    Wait(Duration),
+}
+
+/// Steps toggling DTR and RTS to reset a board or put it into its bootloader.
+/// Either the name of a built-in sequence like `esp32`, or comma separated steps
+/// like `dtr=0,rts=1,wait=100,rts=0` with waits in milliseconds.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(try_from = "String", into = "String")]
+pub struct ResetSequence {
+    source: String,
+    steps: Vec<ResetStep>,
+}
+
+impl ResetSequence {
+    /// Runs the sequence on `port`.
+    fn run(&self, port: &mut dyn SerialPort) -> serialport::Result<()> {
+        for step in &self.steps {
+            match step {
+                ResetStep::Dtr(level) => port.write_data_terminal_ready(*level)?,
+                ResetStep::Rts(level) => port.write_request_to_send(*level)?,
+                ResetStep::Wait(duration) => thread::sleep(*duration),
+            }
+        }
+        Ok(())
+    }
+}
+
+impl FromStr for ResetSequence {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let source = s.trim();
+        let steps = RESET_SEQUENCES
+            .iter()
+            .find(|(name, _)| name.eq_ignore_ascii_case(source))
+            .map_or(source, |(_, steps)| steps);
+
+        let steps = steps
+            .split(',')
+            .map(|step| {
+                let (signal, value) = step.trim().split_once('=').unwrap_or((step, ""));
+                let level = || match value.trim() {
+                    "1" | "on" | "true" => Ok(true),
+                    "0" | "off" | "false" => Ok(false),
+                    _ => Err(format!("Invalid level in reset step \"{step}\"")),
+                };
+                match signal.trim() {
+                    "dtr" => Ok(ResetStep::Dtr(level()?)),
+                    "rts" => Ok(ResetStep::Rts(level()?)),
+                    "wait" => value
+                        .trim()
+                        .parse()
+                        .map(|ms| ResetStep::Wait(Duration::from_millis(ms)))
+                        .map_err(|_| format!("Invalid wait in reset step \"{step}\"")),
+                    _ => Err(format!(
+                        "Invalid reset step \"{step}\", expected dtr=, rts= or wait= \
+                         or one of {}",
+                        RESET_SEQUENCES
+                            .iter()
+                            .map(|(name, _)| *name)
+                            .collect::<Vec<_>>()
+                            .join(", ")
+                    )),
+                }
+            })
+            .collect::<Result<_, _>>()?;
+
+        Ok(Self {
+            source: source.to_owned(),
+            steps,
+        })
+    }
+}
+
+impl TryFrom<String> for ResetSequence {
+    type Error = String;
+
+    fn try_from(value: String) -> Result<Self, Self::Error> {
+        value.parse()
+    }
+}
+
+impl From<ResetSequence> for String {
+    fn from(value: ResetSequence) -> Self {
+        value.source
+    }
+}
+
+impl Display for ResetSequence {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}", self.source)
+    }
+}
+
+/// Modem control lines of a port, as given on the command line or in a profile.
+#[derive(Debug, Clone, Default, clap::Args, Serialize, Deserialize)]
+#[serde(default)]
+pub struct ControlArgs {
+    /// State DTR is set to after opening the port, left to the driver if not given
+    #[arg(long, value_parser = BoolishValueParser::new())]
+    #[serde(skip_serializing_if = "Option::is_none")]
+    dtr: Option<bool>,
+
+    /// State RTS is set to after opening the port, left to the driver if not given
+    #[arg(long, value_parser = BoolishValueParser::new())]
+    #[serde(skip_serializing_if = "Option::is_none")]
+    rts: Option<bool>,
+
+    /// Reset sequence for `:reset`, a name like `esp32`, `esp32-bootloader`,
+    /// `dtr-pulse`, `rts-pulse` or steps like `dtr=0,rts=1,wait=100,rts=0`
+    #[arg(long)]
+    #[serde(skip_serializing_if = "Option::is_none")]
+    reset: Option<ResetSequence>,
+
+    /// Run the reset sequence right after opening the port, to capture the boot log
+    #[arg(long, requires = "reset")]
+    reset_on_open: bool,
+}
+
+impl ControlArgs {
+    /// Fills the values not given on the command line from `profile`.
+    pub fn merge(self, profile: Self) -> Self {
+        let reset_on_open = self.reset_on_open || (self.reset.is_none() && profile.reset_on_open);
+        Self {
+            dtr: self.dtr.or(profile.dtr),
+            rts: self.rts.or(profile.rts),
+            reset: self.reset.or(profile.reset),
+            reset_on_open,
+        }
+    }
+
+    /// Sets up the control lines of a freshly opened port. With `first_open`
+    /// the reset sequence is run if it was asked for.
+    pub fn on_open(&self, port: &mut dyn SerialPort, first_open: bool) -> Result<(), String> {
+        if let Some(level) = self.dtr {
+            port.write_data_terminal_ready(level)
+                .map_err(|e| format!("Failed to set DTR. Error: {e}"))?;
+        }
+        if let Some(level) = self.rts {
+            port.write_request_to_send(level)
+                .map_err(|e| format!("Failed to set RTS. Error: {e}"))?;
+        }
+        if let (Some(reset), true) = (&self.reset, first_open && self.reset_on_open) {
+            reset
+                .run(port)
+                .map_err(|e| format!("Failed to reset the target. Error: {e}"))?;
+        }
+        Ok(())
+    }
+
+    /// Carries out a console command on `port`, returning a note on what was done.
+    pub fn run(
+        &self,
+        port: &mut dyn SerialPort,
+        command: ConsoleCommand,
+    ) -> Result<String, String> {
+        match command {
+            ConsoleCommand::Reset(sequence) => {
+                let Some(sequence) = sequence.as_ref().or(self.reset.as_ref()) else {
+                    return Err("No reset sequence set, use --reset or :reset <sequence>".into());
+                };
+                sequence
+                    .run(port)
+                    .map_err(|e| format!("Failed to reset the target. Error: {e}"))?;
+                Ok(format!("Reset the target ({sequence})"))
+            }
+            ConsoleCommand::Dtr(level) => {
+                port.write_data_terminal_ready(level)
+                    .map_err(|e| format!("Failed to set DTR. Error: {e}"))?;
+                Ok(format!("DTR {}", if level { "on" } else { "off" }))
+            }
+            ConsoleCommand::Rts(level) => {
+                port.write_request_to_send(level)
+                    .map_err(|e| format!("Failed to set RTS. Error: {e}"))?;
+                Ok(format!("RTS {}", if level { "on" } else { "off" }))
+            }
+            ConsoleCommand::Send(_) | ConsoleCommand::Quit => {
+                Err("Sending and quitting are handled by the capture loop".into())
+            }
+        }
+    }
+}
+
+/// A timestamped line noting a change in the connection, e.g. a disconnect.
+pub fn marker_line(message: &str) -> Vec<u8> {
+    marker_line_at(message, Local::now())
+}
+
+/// Like [`marker_line`], with the timestamp of `time` instead of now.
+pub fn marker_line_at(message: &str, time: DateTime<Local>) -> Vec<u8> {
+    let mut data = format_timestamp(time).into_bytes();
+    data.extend_from_slice(
+        format!(
+            "{YELLOW}--- {message} ---{RESET}\n",
+            RESET = "\x1b[0m",
+            YELLOW = "\x1b[33m",
+        )
+        .as_bytes(),
+    );
+    data
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serialport::UsbPortInfo;
+
+    #[test]
+    fn test_usb_selector() {
+        let selector = "usb:10c4:EA60:SERIAL:123".parse::<PortSelector>().unwrap();
+
+        assert_eq!(
+            selector,
+            PortSelector::Usb {
+                vid: 0x10c4,
+                pid: 0xea60,
+                serial_number: Some("SERIAL:123".to_owned()),
+            }
+        );
+        assert_eq!(selector.to_string(), "usb:10c4:ea60:SERIAL:123");
+    }
+
+    #[test]
+    fn test_usb_selector_without_serial() {
+        let selector = "usb:0403:6001".parse::<PortSelector>().unwrap();
+
+        assert_eq!(
+            selector,
+            PortSelector::Usb {
+                vid: 0x0403,
+                pid: 0x6001,
+                serial_number: None,
+            }
+        );
+        assert_eq!(selector.to_string(), "usb:0403:6001");
+    }
+
+    #[test]
+    fn test_invalid_usb_selector() {
+        assert!("usb:10c4".parse::<PortSelector>().is_err());
+        assert!("usb:xyz:ea60".parse::<PortSelector>().is_err());
+    }
+
+    #[test]
+    fn test_frame_format() {
+        let settings = "7e2".parse::<LineSettings>().unwrap();
+
+        assert_eq!(settings.data_bits, 7);
+        assert_eq!(settings.parity, Parity::Even);
+        assert_eq!(settings.stop_bits, 2);
+        assert_eq!(settings.to_string(), "7E2");
+        assert!("9N1".parse::<LineSettings>().is_err());
+        assert!("8X1".parse::<LineSettings>().is_err());
+        assert!("8N".parse::<LineSettings>().is_err());
+    }
+
+    #[test]
+    fn test_reset_sequence() {
+        let sequence = "dtr=0, rts=on,wait=100,rts=off"
+            .parse::<ResetSequence>()
+            .unwrap();
+
+        assert_eq!(
+            sequence.steps,
+            vec![
+                ResetStep::Dtr(false),
+                ResetStep::Rts(true),
+                ResetStep::Wait(Duration::from_millis(100)),
+                ResetStep::Rts(false),
+            ]
+        );
+        assert_eq!(
+            "ESP32".parse::<ResetSequence>().unwrap().steps,
+            "dtr=0,rts=1,wait=100,rts=0"
+                .parse::<ResetSequence>()
+                .unwrap()
+                .steps
+        );
+        assert!("dtr=2".parse::<ResetSequence>().is_err());
+        assert!("wait=soon".parse::<ResetSequence>().is_err());
+        assert!("reboot".parse::<ResetSequence>().is_err());
+    }
+
+    #[test]
+    fn test_port_identity() {
+        let usb = |serial_number: Option<&str>| SerialPortInfo {
+            port_name: "/dev/ttyUSB0".to_owned(),
+            port_type: SerialPortType::UsbPort(UsbPortInfo {
+                vid: 0x0403,
+                pid: 0x6001,
+                serial_number: serial_number.map(str::to_owned),
+                manufacturer: None,
+                product: None,
+            }),
+        };
+        let path = "/dev/serial/by-id/usb-FTDI_FT232R-if00-port0";
+
+        assert_eq!(
+            PortIdentity::selector(path, Some(&usb(Some("A50285BI")))),
+            PortSelector::Usb {
+                vid: 0x0403,
+                pid: 0x6001,
+                serial_number: Some("A50285BI".to_owned()),
+            }
+        );
+        // Any identical adapter would match its IDs
+        assert_eq!(
+            PortIdentity::selector(path, Some(&usb(None))),
+            PortSelector::Path(path.to_owned())
+        );
+        assert_eq!(
+            PortIdentity::selector("/dev/ttyS0", None),
+            PortSelector::Path("/dev/ttyS0".to_owned())
+        );
+    }
+
+    #[test]
+    fn test_path_selector() {
+        let path = "/dev/serial/by-id/usb-Silicon_Labs_CP2102-if00-port0";
+
+        assert_eq!(
+            path.parse::<PortSelector>().unwrap(),
+            PortSelector::Path(path.to_owned())
+        );
+    }
+}
@@ -1,68 +1,529 @@
+use chrono::{DateTime, Local};
 use crossterm::style::Stylize;
 use inquire::validator::Validation;
 use inquire::CustomType;
-use inquire::{InquireError, Select};
-use serialport::available_ports;
-use std::fs::{create_dir_all, OpenOptions};
-use std::io::{self, Write};
-use std::path::Path;
-use std::thread;
+use inquire::InquireError;
+use inquire::{Confirm, Select, Text};
+use serde::{Deserialize, Serialize};
+use std::io::{self, IsTerminal, Write};
+use std::path::{Path, PathBuf};
 use std::time::Duration;
 
-use crate::utils::generate_timestamp;
+use crate::capture::{CaptureInfo, CaptureReader, CaptureWriter, RecordKind};
+use crate::console::{spawn_console, ConsoleCommand, LineEnding};
+use crate::encoding::Encoding;
+use crate::framing::{tag_truncated, Delimiter, LengthArgs, LineEnd, LineFramer};
+use crate::profile;
+use crate::serial::{
+    marker_line, marker_line_at, resolve_port, select_port, ControlArgs, LineArgs, PortIdentity,
+    READ_BUFFER_SIZE,
+};
+use crate::utils::{
+    expand_output_name, format_timestamp, get_baud, get_output, open_output, timestamped_at,
+};
+use crate::writer::OutputWriter;
 
-pub fn slog_main(init: bool) -> Result<(), Box<dyn std::error::Error>> {
-    let options = available_ports().expect("Failed to detect ports");
-    if options.is_empty() {
-        if init {
-            eprintln!("Waiting for serial interfaces...");
+/// Longest line buffered unless set otherwise.
+const DEFAULT_MAX_LENGTH: usize = 64 * 1024;
+
+/// Serial Logger
+#[derive(Debug, Clone, Default, clap::Args, Serialize, Deserialize)]
+#[serde(default)]
+pub struct SlogArgs {
+    /// Saved profile to take the values not given on the command line from
+    #[arg(short = 'P', long)]
+    #[serde(skip)]
+    pub profile: Option<String>,
+
+    /// Serial port to read from, as a path or `usb:VID:PID[:SERIAL]`
+    #[arg(short, long)]
+    #[serde(skip_serializing_if = "Option::is_none")]
+    port: Option<String>,
+
+    /// Chars the received data is split into lines on, escape sequences like `\r\n` or `\x00` are accepted
+    #[arg(short, long)]
+    #[serde(skip_serializing_if = "Option::is_none")]
+    split: Option<String>,
+
+    /// Regex the received data is split into lines on, instead of --split
+    #[arg(long, conflicts_with = "split")]
+    #[serde(skip_serializing_if = "Option::is_none")]
+    split_regex: Option<String>,
+
+    /// Keep the delimiter at the end of each line
+    #[arg(long)]
+    keep_delimiter: bool,
+
+    /// End a line after this many milliseconds without data, without --split or
+    /// --split-regex lines only end this way
+    #[arg(long)]
+    #[serde(skip_serializing_if = "Option::is_none")]
+    idle_gap: Option<u64>,
+
+    /// Baud rate of the port
+    #[arg(short, long)]
+    #[serde(skip_serializing_if = "Option::is_none")]
+    baud: Option<u32>,
+
+    #[command(flatten)]
+    #[serde(flatten)]
+    line: LineArgs,
+
+    #[command(flatten)]
+    #[serde(flatten)]
+    control: ControlArgs,
+
+    #[command(flatten)]
+    #[serde(flatten)]
+    length: LengthArgs,
+
+    /// How received lines are shown and written [default: utf8]
+    #[arg(short, long)]
+    #[serde(skip_serializing_if = "Option::is_none")]
+    encoding: Option<Encoding>,
+
+    /// Send lines typed on the terminal to the device, logged with a TX marker
+    #[arg(long)]
+    terminal: bool,
+
+    /// Line ending sent after each typed line in terminal mode [default: lf]
+    #[arg(long)]
+    #[serde(skip_serializing_if = "Option::is_none")]
+    line_ending: Option<LineEnding>,
+
+    /// Also record everything received under `captures/` with this name, for --replay
+    #[arg(long)]
+    #[serde(skip_serializing_if = "Option::is_none")]
+    capture: Option<String>,
+
+    /// Decode a file recorded with --capture instead of reading a port
+    #[arg(long, conflicts_with_all = ["port", "capture", "terminal"])]
+    #[serde(skip)]
+    replay: Option<PathBuf>,
+
+    /// Replay at the speed the capture was recorded at instead of as fast as possible
+    #[arg(long, requires = "replay")]
+    #[serde(skip)]
+    realtime: bool,
+
+    /// Name of the output file written under `slog/`, strftime specifiers like `%Y%m%d` are expanded
+    #[arg(short, long, conflicts_with = "no_output")]
+    #[serde(skip_serializing_if = "Option::is_none")]
+    output: Option<String>,
+
+    /// Only print to the terminal, without writing an output file
+    #[arg(long)]
+    no_output: bool,
+
+    /// Never prompt, use defaults for missing values and fail if the port is missing
+    #[arg(long)]
+    #[serde(skip)]
+    non_interactive: bool,
+}
+
+impl SlogArgs {
+    /// Fills the values not given on the command line from `profile`.
+    fn merge(self, profile: Self) -> Self {
+        let no_output = self.no_output || (self.output.is_none() && profile.no_output);
+        let (split, split_regex) = if self.split.is_some() || self.split_regex.is_some() {
+            (self.split, self.split_regex)
+        } else {
+            (profile.split, profile.split_regex)
+        };
+        Self {
+            port: self.port.or(profile.port),
+            split,
+            split_regex,
+            keep_delimiter: self.keep_delimiter || profile.keep_delimiter,
+            idle_gap: self.idle_gap.or(profile.idle_gap),
+            baud: self.baud.or(profile.baud),
+            line: self.line.merge(profile.line),
+            control: self.control.merge(profile.control),
+            length: self.length.merge(profile.length),
+            encoding: self.encoding.or(profile.encoding),
+            terminal: self.terminal || profile.terminal,
+            line_ending: self.line_ending.or(profile.line_ending),
+            capture: self.capture.or(profile.capture),
+            output: self.output.or(profile.output),
+            no_output,
+            ..self
         }
-        thread::sleep(Duration::from_millis(100));
-        return slog_main(false);
-    }
-
-    let port_path = match Select::new(
-        "Select the port to read from:",
-        options
-            .clone()
-            .into_iter()
-            .map(|o| {
-                if o.port_name.starts_with("/sys/class/tty/") {
-                    o.port_name.replace("/sys/class/tty/", "/dev/")
-                } else {
-                    o.port_name
-                }
-            })
-            .collect(),
-    )
-    .prompt()
-    {
-        Ok(k) => k,
-        Err(InquireError::OperationInterrupted) => return Ok(()),
-        Err(_) => return slog_main(true), // Restarts to check for more iterfaces.
+    }
+}
+
+pub fn slog_main(args: SlogArgs) -> Result<(), Box<dyn std::error::Error>> {
+    let args = match &args.profile {
+        Some(name) => args.clone().merge(profile::load("slog", name)?),
+        None => args,
     };
-    let split_char_result: Option<String> = loop {
-        match CustomType::new("Select the split char:")
-            .with_help_message("esc for default")
-            .with_validator(|a: &String| {
-                if let Some(escaped_char) = process_escape_sequence(a) {
-                    if escaped_char.is_ascii() {
-                        return Ok(Validation::Valid);
+    if let Some(path) = &args.replay {
+        return replay(&args, path);
+    }
+    let mut prompted = false;
+
+    let port_selector = match &args.port {
+        Some(port) => port.clone(),
+        None if args.non_interactive => {
+            return Err("--port is required with --non-interactive".into())
+        }
+        None => match select_port() {
+            Some(port) => {
+                prompted = true;
+                port
+            }
+            None => return Ok(()),
+        },
+    };
+
+    let (split, split_regex, keep_delimiter) = match (&args.split, &args.split_regex) {
+        (_, Some(pattern)) => (None, Some(pattern.clone()), args.keep_delimiter),
+        (Some(split), None) => (Some(split.clone()), None, args.keep_delimiter),
+        (None, None) if args.idle_gap.is_some() => (None, None, args.keep_delimiter),
+        (None, None) if args.non_interactive => (Some("\\n".to_owned()), None, args.keep_delimiter),
+        (None, None) => {
+            let Some((split, split_regex)) = get_split() else {
+                return Ok(());
+            };
+            let keep_delimiter = match args.keep_delimiter {
+                true => true,
+                false => match get_keep_delimiter() {
+                    Some(keep_delimiter) => keep_delimiter,
+                    None => return Ok(()),
+                },
+            };
+            prompted = true;
+            (split, split_regex, keep_delimiter)
+        }
+    };
+    let delimiter = delimiter(split.as_deref(), split_regex.as_deref())?;
+
+    let baud = match args.baud {
+        Some(baud) => baud,
+        None if args.non_interactive => 115200,
+        None => match get_baud() {
+            Some(baud) => {
+                prompted = true;
+                baud
+            }
+            None => return Ok(()),
+        },
+    };
+
+    let Some(line) = args.line.resolve(args.non_interactive, &mut prompted) else {
+        return Ok(());
+    };
+
+    let output: Option<String> = match &args.output {
+        _ if args.no_output => None,
+        Some(output) => Some(output.clone()),
+        None if args.non_interactive => None,
+        None => match get_output() {
+            Some(output) => {
+                prompted = true;
+                output
+            }
+            None => return Ok(()),
+        },
+    };
+
+    if prompted {
+        let profile = SlogArgs {
+            port: Some(port_selector.clone()),
+            split,
+            split_regex,
+            keep_delimiter,
+            idle_gap: args.idle_gap,
+            baud: Some(baud),
+            line: line.to_args(),
+            control: args.control.clone(),
+            length: args.length.clone(),
+            encoding: args.encoding,
+            terminal: args.terminal,
+            line_ending: args.line_ending,
+            capture: args.capture.clone(),
+            no_output: output.is_none(),
+            output: output.clone(),
+            ..Default::default()
+        };
+        profile::offer_save("slog", &profile, args.profile.as_deref());
+    }
+    let output = output.map(|output| expand_output_name(&output));
+    let line_ending = args.line_ending.unwrap_or_default();
+    let port_path = resolve_port(&port_selector)?;
+
+    let builder = line.apply(serialport::new(&port_path, baud).timeout(Duration::from_millis(10)));
+    let identity = PortIdentity::of(&port_path);
+
+    match builder.clone().open() {
+        Ok(mut port) => {
+            let mut serial_buf = vec![0; READ_BUFFER_SIZE];
+            println!(
+                "Receiving data on {} at {} baud, {}:",
+                &port_path, baud, line
+            );
+            if let Err(e) = args.control.on_open(port.as_mut(), true) {
+                eprintln!("{e}");
+            }
+            if !args.non_interactive && io::stdin().is_terminal() {
+                println!("Type :help and press enter for commands.");
+            }
+            let file = output.map(|output| open_output("slog", &output));
+            let writer = OutputWriter::spawn(file, true);
+            let capture = args.capture.as_ref().map(|name| {
+                let info = CaptureInfo {
+                    mode: "slog".to_owned(),
+                    port: port_path.clone(),
+                    baud,
+                    line: line.to_string(),
+                };
+                CaptureWriter::create(&expand_output_name(name), &info)
+            });
+            let record = |kind: RecordKind, data: &[u8]| {
+                if let Some(capture) = &capture {
+                    capture.record(kind, data);
+                }
+            };
+            let marker = |message: &str| {
+                writer.write(marker_line(message));
+                record(RecordKind::Marker, message.as_bytes());
+            };
+            let commands =
+                spawn_console(args.terminal.then_some(line_ending), !args.non_interactive);
+            let mut decoder = LineDecoder::new(&args, delimiter, keep_delimiter);
+
+            'capture: loop {
+                match port.read(&mut serial_buf) {
+                    Ok(n) => {
+                        record(RecordKind::Received, &serial_buf[..n]);
+                        decoder.push(&serial_buf[..n], Local::now(), &writer);
+                    }
+                    Err(ref e) if e.kind() == io::ErrorKind::TimedOut => (),
+                    Err(ref e) if e.kind() == io::ErrorKind::BrokenPipe => {
+                        // Keep the partial line and wait for the same device to come back.
+                        marker(&format!("Disconnected, waiting for {port_path}"));
+
+                        let Some((path, new_port)) = identity.reconnect(&builder, &commands) else {
+                            break 'capture;
+                        };
+                        port = new_port;
+                        if let Err(e) = args.control.on_open(port.as_mut(), false) {
+                            eprintln!("{e}");
+                        }
+
+                        marker(&format!("Reconnected on {path}"));
+                    }
+                    Err(e) => eprintln!("{e:?}"),
+                }
+                decoder.flush_idle(Local::now(), &writer);
+
+                while let Ok(command) = commands.try_recv() {
+                    match command {
+                        ConsoleCommand::Send(data) => match port.write_all(&data) {
+                            Ok(()) => {
+                                writer.write(tx_line(&data, Local::now()));
+                                record(RecordKind::Sent, &data);
+                            }
+                            Err(e) => eprintln!("Failed to send to \"{port_path}\". Error: {e}"),
+                        },
+                        ConsoleCommand::Quit => break 'capture,
+                        command => match args.control.run(port.as_mut(), command) {
+                            Ok(message) => marker(&message),
+                            Err(e) => eprintln!("{e}"),
+                        },
                     }
                 }
+            }
 
-                if a.len() == 1 && a.chars().next().unwrap().is_ascii() {
+            decoder.flush(&writer);
+            writer.write(marker_line(&decoder.summary()));
+            Ok(())
+        }
+        Err(e) => {
+            eprintln!("Failed to open \"{}\". Error: {}", &port_path, e);
+            ::std::process::exit(1);
+        }
+    }
+}
+
+/// Decodes the capture at `path` as if it was received again, without prompting.
+fn replay(args: &SlogArgs, path: &Path) -> Result<(), Box<dyn std::error::Error>> {
+    let reader = CaptureReader::open(path)?;
+    let info = reader.info();
+    println!(
+        "Replaying {} captured on {} at {} baud, {}:",
+        path.display(),
+        info.port,
+        info.baud,
+        info.line
+    );
+
+    let split = match (&args.split, &args.split_regex, args.idle_gap) {
+        (None, None, None) => Some("\\n"),
+        (split, _, _) => split.as_deref(),
+    };
+    let delimiter = delimiter(split, args.split_regex.as_deref())?;
+    let output = match &args.output {
+        Some(output) if !args.no_output => Some(expand_output_name(output)),
+        _ => None,
+    };
+    let file = output.map(|output| open_output("slog", &output));
+    let writer = OutputWriter::spawn(file, true);
+    let mut decoder = LineDecoder::new(args, delimiter, args.keep_delimiter);
+
+    reader.replay(args.realtime, |record| {
+        decoder.flush_idle(record.time, &writer);
+        match record.kind {
+            RecordKind::Received => decoder.push(&record.data, record.time, &writer),
+            RecordKind::Sent => writer.write(tx_line(&record.data, record.time)),
+            RecordKind::Marker => {
+                let message = String::from_utf8_lossy(&record.data);
+                writer.write(marker_line_at(&message, record.time))
+            }
+        }
+    })?;
+
+    // The device went quiet at the end of the capture
+    decoder.flush_idle(Local::now(), &writer);
+    decoder.flush(&writer);
+    writer.write(marker_line(&decoder.summary()));
+    Ok(())
+}
+
+/// The delimiter given by `split` or `split_regex`, or none to only end lines on idle gaps.
+fn delimiter(split: Option<&str>, split_regex: Option<&str>) -> Result<Delimiter, String> {
+    match (split, split_regex) {
+        (Some(split), _) => parse_split(split)
+            .map(Delimiter::Bytes)
+            .ok_or(format!("Invalid delimiter \"{split}\"")),
+        (None, Some(pattern)) => Delimiter::regex(pattern),
+        (None, None) => Ok(Delimiter::None),
+    }
+}
+
+/// Turns received bytes into timestamped lines, for a port and a replayed capture alike.
+struct LineDecoder {
+    framer: LineFramer,
+    encoding: Encoding,
+    idle_gap: Option<Duration>,
+}
+
+impl LineDecoder {
+    fn new(args: &SlogArgs, delimiter: Delimiter, keep_delimiter: bool) -> Self {
+        let framer = LineFramer::new(
+            delimiter,
+            args.length.max_length(DEFAULT_MAX_LENGTH),
+            args.length.overflow(),
+        )
+        .keep_delimiter(keep_delimiter);
+
+        Self {
+            framer,
+            encoding: args.encoding.unwrap_or_default(),
+            idle_gap: args.idle_gap.map(Duration::from_millis),
+        }
+    }
+
+    fn push(&mut self, chunk: &[u8], received: DateTime<Local>, writer: &OutputWriter) {
+        let encoding = self.encoding;
+        self.framer.push(chunk, received, |line, end| {
+            writer.write(rx_line(line, end, encoding, received))
+        });
+    }
+
+    /// Ends the pending line if nothing was received for the idle gap before `now`.
+    fn flush_idle(&mut self, now: DateTime<Local>, writer: &OutputWriter) {
+        if let Some(idle_gap) = self.idle_gap {
+            let encoding = self.encoding;
+            self.framer.flush_idle(idle_gap, now, |line, end| {
+                writer.write(rx_line(line, end, encoding, now))
+            });
+        }
+    }
+
+    /// Ends the pending line, at the end of the session or capture.
+    fn flush(&mut self, writer: &OutputWriter) {
+        let encoding = self.encoding;
+        let now = Local::now();
+        self.framer
+            .flush(|line, end| writer.write(rx_line(line, end, encoding, now)));
+    }
+
+    fn summary(&self) -> String {
+        format!("Lines over the maximum length: {}", self.framer.counts())
+    }
+}
+
+/// A timestamped line showing `line` in `encoding`, which ended at `received`.
+fn rx_line(line: &[u8], end: LineEnd, encoding: Encoding, received: DateTime<Local>) -> Vec<u8> {
+    let line = encoding.render(line);
+    let time = match end {
+        LineEnd::Idle(started) => started,
+        LineEnd::Delimiter | LineEnd::Truncated => received,
+    };
+    let line = encoding
+        .rows(&line)
+        .into_iter()
+        .flat_map(|row| timestamped_at(row, time))
+        .collect();
+    match end {
+        LineEnd::Truncated => tag_truncated(line),
+        LineEnd::Delimiter | LineEnd::Idle(_) => line,
+    }
+}
+
+/// A timestamped line showing what was sent to the device at `time`.
+fn tx_line(sent: &[u8], time: DateTime<Local>) -> Vec<u8> {
+    let text = String::from_utf8_lossy(sent);
+    let mut data = format_timestamp(time).into_bytes();
+    data.extend_from_slice(
+        format!(
+            "{MAGENTA}[TX]{RESET} {}\n",
+            text.trim_end_matches(['\r', '\n']),
+            RESET = "\x1b[0m",
+            MAGENTA = "\x1b[35m",
+        )
+        .as_bytes(),
+    );
+    data
+}
+
+/// Asks whether lines are split on chars or on a regex, and for the one chosen.
+/// Returns the `split` and `split_regex` values, only one of them set.
+fn get_split() -> Option<(Option<String>, Option<String>)> {
+    const CHARS: &str = "Chars";
+    const REGEX: &str = "Regex";
+
+    loop {
+        match Select::new("Split the received data into lines on:", vec![CHARS, REGEX])
+            .with_help_message("esc for default")
+            .prompt_skippable()
+        {
+            Ok(Some(REGEX)) => break get_split_regex().map(|pattern| (None, Some(pattern))),
+            Ok(Some(_)) => break get_split_chars().map(|split| (Some(split), None)),
+            Ok(None) => break Some((Some("\\n".to_owned()), None)),
+            Err(InquireError::OperationInterrupted) => break None,
+            Err(_) => eprintln!("{}", "Please select an option.".red().slow_blink()),
+        }
+    }
+}
+
+fn get_split_chars() -> Option<String> {
+    let split_result: Option<String> = loop {
+        match CustomType::new("Select the split chars:")
+            .with_help_message("esc for default, e.g. \\r\\n or \\x00")
+            .with_validator(|a: &String| {
+                if parse_split(a).is_some() {
                     Ok(Validation::Valid)
                 } else {
                     Ok(Validation::Invalid(inquire::validator::ErrorMessage::from(
-                        "Split char must be ascii or a valid escape sequence".to_owned(),
+                        "Split chars must be text or valid escape sequences".to_owned(),
                     )))
                 }
             })
             .prompt_skippable()
         {
             Ok(k) => break k,
-            Err(InquireError::OperationInterrupted) => return Ok(()),
+            Err(InquireError::OperationInterrupted) => return None,
             Err(_) => {
                 eprintln!("{}", "Please type a correct value".red().slow_blink());
                 continue;
@@ -70,117 +531,68 @@ pub fn slog_main(init: bool) -> Result<(), Box<dyn std::error::Error>> {
         }
     };
 
-    let split_char: char = if let Some(k) = split_char_result {
-        if k.len() == 1 {
-            k.chars().next().unwrap()
-        } else {
-            process_escape_sequence(&k).unwrap()
-        }
-    } else {
-        '\n'
-    };
+    Some(split_result.unwrap_or_else(|| "\\n".to_owned()))
+}
 
-    let baud = loop {
-        match CustomType::new("What is the baud rate?:")
-            .with_error_message("Please type a valid number")
-            .with_help_message("esc for default")
-            .prompt_skippable()
+fn get_split_regex() -> Option<String> {
+    loop {
+        match Text::new("Enter the split regex:")
+            .with_help_message("e.g. \\r?\\n or \\x00+")
+            .with_validator(|input: &str| {
+                Ok(match Delimiter::regex(input) {
+                    Ok(_) => Validation::Valid,
+                    Err(e) => Validation::Invalid(e.into()),
+                })
+            })
+            .prompt()
         {
-            Ok(ans) => break ans,
-            Err(InquireError::OperationInterrupted) => return Ok(()),
+            Ok(ans) => break Some(ans),
+            Err(InquireError::OperationInterrupted) => break None,
             Err(_) => eprintln!("{}", "Please type a correct value".red().slow_blink()),
         }
     }
-    .unwrap_or(115200);
+}
 
-    let output: Option<String> = loop {
-        match CustomType::new("What is the output file name?:")
-            .with_error_message("Please type a valid file name")
-            .with_help_message("esc to skip outputing to a file")
-            .prompt_skippable()
+fn get_keep_delimiter() -> Option<bool> {
+    loop {
+        match Confirm::new("Keep the delimiter at the end of each line?")
+            .with_default(false)
+            .prompt()
         {
-            Ok(ans) => break ans,
-            Err(InquireError::OperationInterrupted) => return Ok(()),
+            Ok(ans) => break Some(ans),
+            Err(InquireError::OperationInterrupted) => break None,
             Err(_) => eprintln!("{}", "Please type a correct value".red().slow_blink()),
         }
-    };
+    }
+}
 
-    let port = serialport::new(&port_path, baud)
-        .timeout(Duration::from_millis(10))
-        .open();
+/// Parses text mixed with escape sequences such as `\r\n` or `\x00\xFF` into the
+/// bytes of a delimiter.
+fn parse_split(s: &str) -> Option<Vec<u8>> {
+    let mut bytes = Vec::new();
+    let mut rest = s;
 
-    match port {
-        Ok(mut port) => {
-            let mut serial_buf = [0; 1];
-            println!("Receiving data on {} at {} baud:", &port_path, baud);
-            let mut accumulated_data = Vec::new();
-
-            loop {
-                match port.read(serial_buf.as_mut_slice()) {
-                    Ok(_) => {
-                        accumulated_data.extend_from_slice(&serial_buf[..]);
-
-                        // Split the accumulated data by newlines
-                        while let Some(pos) =
-                            accumulated_data.iter().position(|&x| x == split_char as u8)
-                        {
-                            let mut line = accumulated_data.drain(..=pos).collect::<Vec<u8>>();
-                            line.pop();
-
-                            let timestamp = generate_timestamp().into_bytes();
-
-                            let mut data = Vec::with_capacity(timestamp.len() + line.len() + 1);
-                            data.extend_from_slice(&timestamp);
-                            data.extend_from_slice(&line);
-                            data.extend_from_slice(String::from("\n").as_bytes());
-
-                            if let Ok(string) = std::str::from_utf8(&data) {
-                                print!("{}", string);
-                            } else {
-                                eprintln!("Bytes are not valid UTF-8");
-                            }
-                            if let Some(ref file) = &output {
-                                if !Path::new("slog").exists() {
-                                    create_dir_all("slog").expect("Unable to create dir");
-                                }
-
-                                let mut file = match OpenOptions::new()
-                                    .write(true)
-                                    .append(true)
-                                    .create(true)
-                                    .open(format!("slog/{file}.txt"))
-                                {
-                                    Ok(file) => file,
-                                    Err(e) => {
-                                        eprintln!(
-                                            "Failed to open \"{}\". Error: {}",
-                                            output.as_ref().unwrap().as_str(),
-                                            e
-                                        );
-                                        ::std::process::exit(1);
-                                    }
-                                };
-                                file.write_all(&data).unwrap();
-                                file.flush().unwrap();
-                            }
-                        }
-                    }
-                    Err(ref e) if e.kind() == io::ErrorKind::TimedOut => (),
-                    Err(ref e) if e.kind() == io::ErrorKind::BrokenPipe => return slog_main(true), // Restart
-                    Err(e) => eprintln!("{e:?}"),
-                }
-            }
-        }
-        Err(e) => {
-            eprintln!("Failed to open \"{}\". Error: {}", &port_path, e);
-            ::std::process::exit(1);
+    while let Some(c) = rest.chars().next() {
+        let len = match c {
+            '\\' if rest[1..].starts_with('x') => 4,
+            '\\' => 2,
+            _ => c.len_utf8(),
+        };
+        let token = rest.get(..len)?;
+        match process_escape_sequence(token) {
+            Some(escaped) => bytes.push(escaped as u8),
+            None if c == '\\' => return None,
+            None => bytes.extend_from_slice(token.as_bytes()),
         }
+        rest = &rest[len..];
     }
+
+    (!bytes.is_empty()).then_some(bytes)
 }
 
 fn process_escape_sequence(s: &str) -> Option<char> {
     match s {
-        "\'" => Some('\''),
+        "\\'" => Some('\''),
         "\\\"" => Some('\"'),
         "\\\\" => Some('\\'),
         "\\n" => Some('\n'),
@@ -190,7 +602,8 @@ fn process_escape_sequence(s: &str) -> Option<char> {
         "\\f" => Some('\u{000C}'), // form feed
         "\\v" => Some('\u{000B}'), // vertical tab
         "\\0" => Some('\u{0000}'), // null character
-        _ if s.starts_with("\\x") => {
+        // from_str_radix alone would take a sign like `\x+1`
+        _ if s.starts_with("\\x") && s[2..].bytes().all(|b| b.is_ascii_hexdigit()) => {
             let hex_part = &s[2..];
             u8::from_str_radix(hex_part, 16)
                 .ok()
@@ -199,3 +612,40 @@ fn process_escape_sequence(s: &str) -> Option<char> {
         _ => None,
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_split() {
+        assert_eq!(parse_split("\\n"), Some(b"\n".to_vec()));
+        assert_eq!(parse_split("\\r\\n"), Some(b"\r\n".to_vec()));
+        assert_eq!(parse_split("\\x00\\xFF"), Some(vec![0x00, 0xFF]));
+        assert_eq!(parse_split("> "), Some(b"> ".to_vec()));
+        assert_eq!(parse_split(""), None);
+        assert_eq!(parse_split("\\q"), None);
+        assert_eq!(parse_split("\\x0"), None);
+        assert_eq!(parse_split("\\x+1"), None);
+        assert_eq!(parse_split("\\x-1"), None);
+        assert_eq!(parse_split("\\xg0"), None);
+        assert_eq!(parse_split("\\x0é"), None);
+        assert_eq!(parse_split("\\'"), Some(b"'".to_vec()));
+    }
+
+    #[test]
+    fn test_hex_rows_timestamped() {
+        let time = Local::now();
+        let timestamp = format_timestamp(time);
+        let line = rx_line(&[0; 20], LineEnd::Delimiter, Encoding::Hex, time);
+        let line = String::from_utf8(line).unwrap();
+        let rows: Vec<_> = line.lines().collect();
+        assert_eq!(rows.len(), 2);
+        assert!(rows.iter().all(|row| row.starts_with(&timestamp)));
+        assert!(rows[1][timestamp.len()..].starts_with("00000010 "));
+
+        // Other encodings keep a line with newlines in it under one timestamp
+        let line = rx_line(b"a\nb", LineEnd::Delimiter, Encoding::Lossy, time);
+        assert_eq!(line, [timestamp.as_bytes(), b"a\nb\n"].concat());
+    }
+}
@@ -1,312 +1,1819 @@
-#![allow(dead_code)]
-
 use std::{
-    fs::{create_dir_all, OpenOptions},
-    io::{self, Write},
-    path::Path,
-    thread,
-    time::{Duration, Instant},
+    borrow::Cow,
+    collections::{HashMap, VecDeque},
+    fmt::Display,
+    io::{self, IsTerminal, Write},
+    path::{Path, PathBuf},
+    str::{FromStr, Utf8Error},
+    time::Duration,
 };
 
-use anyhow::{anyhow, Result};
+use chrono::{DateTime, Local};
+use clap::ValueEnum;
 use crossterm::style::Stylize;
-use inquire::{CustomType, InquireError, Select};
-use serialport::available_ports;
+use inquire::{CustomType, InquireError};
+use memchr::memchr;
+use serde::{Deserialize, Serialize};
+
+use crate::capture::{CaptureInfo, CaptureReader, CaptureWriter, RecordKind};
+use crate::console::{spawn_console, ConsoleCommand, LineEnding};
+use crate::deferred::FormatTable;
+use crate::encoding::Encoding;
+use crate::framing::{tag_truncated, LengthArgs, Overflow, OverflowCounts};
+use crate::profile;
+use crate::serial::{
+    marker_line, marker_line_at, resolve_port, select_port, ControlArgs, LineArgs, PortIdentity,
+    READ_BUFFER_SIZE,
+};
+use crate::utils::{expand_output_name, format_timestamp, get_baud, get_output, open_output};
+use crate::writer::OutputWriter;
+use tlog_frame::{
+    crc16, fields, flags, payload_type, EncodeError, Header, CRC_LENGTH, HEADER_LENGTH, MARKER,
+    MAX_FRAME_LENGTH, MAX_PREFIX_LENGTH,
+};
+
+pub use tlog_frame::FrameVersion;
+
+/// Device clock ticks per second, microseconds.
+const DEFAULT_TICK_RATE: u64 = 1_000_000;
+/// Version of the command frames sent to the device, numbered and checked.
+const COMMAND_VERSION: FrameVersion = FrameVersion::V3;
+/// Most commands waiting for a reply, the oldest are given up on first.
+const MAX_PENDING_COMMANDS: usize = 256;
+
+/// TLog Logger
+#[derive(Debug, Clone, Default, clap::Args, Serialize, Deserialize)]
+#[serde(default)]
+pub struct TlogArgs {
+    /// Saved profile to take the values not given on the command line from
+    #[arg(short = 'P', long)]
+    #[serde(skip)]
+    pub profile: Option<String>,
+
+    /// Serial port to read from, as a path or `usb:VID:PID[:SERIAL]`
+    #[arg(short, long)]
+    #[serde(skip_serializing_if = "Option::is_none")]
+    port: Option<String>,
+
+    /// Baud rate of the port
+    #[arg(short, long)]
+    #[serde(skip_serializing_if = "Option::is_none")]
+    baud: Option<u32>,
+
+    #[command(flatten)]
+    #[serde(flatten)]
+    line: LineArgs,
+
+    #[command(flatten)]
+    #[serde(flatten)]
+    control: ControlArgs,
+
+    #[command(flatten)]
+    #[serde(flatten)]
+    length: LengthArgs,
+
+    /// How payloads are shown and written [default: utf8]
+    #[arg(short, long)]
+    #[serde(skip_serializing_if = "Option::is_none")]
+    encoding: Option<Encoding>,
+
+    /// Name and color of a payload type byte as `TYPE=NAME[:COLOR]`, like `6=Notice:blue`,
+    /// can be repeated
+    #[arg(long = "level", value_name = "TYPE=NAME[:COLOR]")]
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    levels: Vec<TagName>,
+
+    /// Least severe level printed to the terminal [default: trace]
+    #[arg(long)]
+    #[serde(skip_serializing_if = "Option::is_none")]
+    console_level: Option<Level>,
+
+    /// Least severe level written to the output file [default: trace]
+    #[arg(long)]
+    #[serde(skip_serializing_if = "Option::is_none")]
+    file_level: Option<Level>,
+
+    /// Write a file per level instead of a single one, like `tlog/run1.error.txt`
+    #[arg(long, conflicts_with = "no_output")]
+    split_levels: bool,
+
+    /// Name and color of a channel as `ID=NAME[:COLOR]`, like `1=radio:blue`, can be repeated
+    #[arg(long = "channel-name", value_name = "ID=NAME[:COLOR]")]
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    channel_names: Vec<TagName>,
+
+    /// Only show and write frames of these channels, by id or name, frames without a
+    /// channel are channel 0
+    #[arg(long, value_delimiter = ',')]
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    channels: Vec<String>,
+
+    /// Write a file per channel instead of a single one, like `tlog/run1.radio.txt`
+    #[arg(long, conflicts_with = "no_output")]
+    split_channels: bool,
+
+    /// Firmware ELF file to read the format strings of deferred payloads from, in its
+    /// `.tlog_fmt` section
+    #[arg(long, value_name = "PATH")]
+    #[serde(skip_serializing_if = "Option::is_none")]
+    elf: Option<PathBuf>,
+
+    /// Rate the device clock ticks of version 4 frames and later count at, in Hz [default: 1000000]
+    #[arg(long, value_parser = clap::value_parser!(u64).range(1..))]
+    #[serde(skip_serializing_if = "Option::is_none")]
+    tick_rate: Option<u64>,
+
+    /// Show device times as wall clock times, mapped from the host time of the first
+    /// frame with ticks, instead of device uptimes
+    #[arg(long)]
+    device_clock: bool,
+
+    /// Seconds to wait for an incomplete packet before dropping it
+    #[arg(short, long)]
+    #[serde(skip_serializing_if = "Option::is_none")]
+    timeout: Option<u64>,
+
+    /// Send lines typed on the terminal to the device as command frames, logged with
+    /// the replies to them
+    #[arg(long)]
+    terminal: bool,
+
+    /// Also record everything received under `captures/` with this name, for --replay
+    #[arg(long)]
+    #[serde(skip_serializing_if = "Option::is_none")]
+    capture: Option<String>,
+
+    /// Decode a file recorded with --capture instead of reading a port
+    #[arg(long, conflicts_with_all = ["port", "capture", "terminal"])]
+    #[serde(skip)]
+    replay: Option<PathBuf>,
+
+    /// Replay at the speed the capture was recorded at instead of as fast as possible
+    #[arg(long, requires = "replay")]
+    #[serde(skip)]
+    realtime: bool,
+
+    /// Name of the output file written under `tlog/`, strftime specifiers like `%Y%m%d` are expanded
+    #[arg(short, long, conflicts_with = "no_output")]
+    #[serde(skip_serializing_if = "Option::is_none")]
+    output: Option<String>,
+
+    /// Only print to the terminal, without writing an output file
+    #[arg(long)]
+    no_output: bool,
+
+    /// Never prompt, use defaults for missing values and fail if the port is missing
+    #[arg(long)]
+    #[serde(skip)]
+    non_interactive: bool,
+}
+
+impl TlogArgs {
+    /// Fills the values not given on the command line from `profile`.
+    fn merge(self, profile: Self) -> Self {
+        let no_output = self.no_output || (self.output.is_none() && profile.no_output);
+        Self {
+            port: self.port.or(profile.port),
+            baud: self.baud.or(profile.baud),
+            line: self.line.merge(profile.line),
+            control: self.control.merge(profile.control),
+            length: self.length.merge(profile.length),
+            encoding: self.encoding.or(profile.encoding),
+            levels: match self.levels.is_empty() {
+                true => profile.levels,
+                false => self.levels,
+            },
+            console_level: self.console_level.or(profile.console_level),
+            file_level: self.file_level.or(profile.file_level),
+            split_levels: self.split_levels || profile.split_levels,
+            channel_names: match self.channel_names.is_empty() {
+                true => profile.channel_names,
+                false => self.channel_names,
+            },
+            channels: match self.channels.is_empty() {
+                true => profile.channels,
+                false => self.channels,
+            },
+            split_channels: self.split_channels || profile.split_channels,
+            elf: self.elf.or(profile.elf),
+            tick_rate: self.tick_rate.or(profile.tick_rate),
+            device_clock: self.device_clock || profile.device_clock,
+            timeout: self.timeout.or(profile.timeout),
+            terminal: self.terminal || profile.terminal,
+            capture: self.capture.or(profile.capture),
+            output: self.output.or(profile.output),
+            no_output,
+            ..self
+        }
+    }
+}
+
+pub fn tlog_main(args: TlogArgs) -> Result<(), Box<dyn std::error::Error>> {
+    let args = match &args.profile {
+        Some(name) => args.clone().merge(profile::load("tlog", name)?),
+        None => args,
+    };
+    if let Some(path) = &args.replay {
+        return replay(&args, path);
+    }
+    let mut prompted = false;
+
+    let port_selector = match &args.port {
+        Some(port) => port.clone(),
+        None if args.non_interactive => {
+            return Err("--port is required with --non-interactive".into())
+        }
+        None => match select_port() {
+            Some(port) => {
+                prompted = true;
+                port
+            }
+            None => return Ok(()),
+        },
+    };
+
+    let baud = match args.baud {
+        Some(baud) => baud,
+        None if args.non_interactive => 115200,
+        None => match get_baud() {
+            Some(baud) => {
+                prompted = true;
+                baud
+            }
+            None => return Ok(()),
+        },
+    };
+
+    let Some(line) = args.line.resolve(args.non_interactive, &mut prompted) else {
+        return Ok(());
+    };
+
+    let time_out = match args.timeout {
+        Some(time_out) => time_out,
+        None if args.non_interactive => 5,
+        None => match get_time_out() {
+            Some(time_out) => {
+                prompted = true;
+                time_out
+            }
+            None => return Ok(()),
+        },
+    };
+
+    let output: Option<String> = match &args.output {
+        _ if args.no_output => None,
+        Some(output) => Some(output.clone()),
+        None if args.non_interactive => None,
+        None => match get_output() {
+            Some(output) => {
+                prompted = true;
+                output
+            }
+            None => return Ok(()),
+        },
+    };
+
+    if prompted {
+        let profile = TlogArgs {
+            port: Some(port_selector.clone()),
+            baud: Some(baud),
+            line: line.to_args(),
+            control: args.control.clone(),
+            length: args.length.clone(),
+            encoding: args.encoding,
+            levels: args.levels.clone(),
+            console_level: args.console_level,
+            file_level: args.file_level,
+            split_levels: args.split_levels,
+            channel_names: args.channel_names.clone(),
+            channels: args.channels.clone(),
+            split_channels: args.split_channels,
+            elf: args.elf.clone(),
+            tick_rate: args.tick_rate,
+            device_clock: args.device_clock,
+            timeout: Some(time_out),
+            terminal: args.terminal,
+            capture: args.capture.clone(),
+            no_output: output.is_none(),
+            output: output.clone(),
+            ..Default::default()
+        };
+        profile::offer_save("tlog", &profile, args.profile.as_deref());
+    }
+    let output = output.map(|output| expand_output_name(&output));
+    let port_path = resolve_port(&port_selector)?;
+
+    let builder = line.apply(serialport::new(&port_path, baud).timeout(Duration::from_millis(10)));
+    let identity = PortIdentity::of(&port_path);
+
+    match builder.clone().open() {
+        Ok(mut port) => {
+            let mut serial_buf = vec![0; READ_BUFFER_SIZE];
+            println!(
+                "Receiving data on {} at {} baud, {}:",
+                &port_path, baud, line
+            );
+            if let Err(e) = args.control.on_open(port.as_mut(), true) {
+                eprintln!("{e}");
+            }
+            if !args.non_interactive && io::stdin().is_terminal() {
+                println!("Type :help and press enter for commands.");
+            }
+            let mut output = TlogOutput::new(&args, output)?;
+            let capture = args.capture.as_ref().map(|name| {
+                let info = CaptureInfo {
+                    mode: "tlog".to_owned(),
+                    port: port_path.clone(),
+                    baud,
+                    line: line.to_string(),
+                };
+                CaptureWriter::create(&expand_output_name(name), &info)
+            });
+            let marker = |output: &mut TlogOutput, message: &str| {
+                output.marker(marker_line(message));
+                if let Some(capture) = &capture {
+                    capture.record(RecordKind::Marker, message.as_bytes());
+                }
+            };
+            // Typed lines are framed as they are, without a line ending
+            let commands = spawn_console(
+                args.terminal.then_some(LineEnding::None),
+                !args.non_interactive,
+            );
+            let mut decoder = decoder(&args, time_out);
+
+            'capture: loop {
+                match port.read(&mut serial_buf) {
+                    Ok(n) => {
+                        if let Some(capture) = &capture {
+                            capture.record(RecordKind::Received, &serial_buf[..n]);
+                        }
+                        let received = Local::now();
+                        decoder.push(&serial_buf[..n], received, |event| {
+                            write_event(event, received, &mut output)
+                        });
+                    }
+                    Err(ref e) if e.kind() == io::ErrorKind::TimedOut => {
+                        let now = Local::now();
+                        decoder.check_timeout(now, |event| write_event(event, now, &mut output));
+                    }
+                    Err(ref e) if e.kind() == io::ErrorKind::BrokenPipe => {
+                        // Keep the partial packet and wait for the same device to come back.
+                        marker(
+                            &mut output,
+                            &format!("Disconnected, waiting for {port_path}"),
+                        );
+
+                        let Some((path, new_port)) = identity.reconnect(&builder, &commands) else {
+                            break 'capture;
+                        };
+                        port = new_port;
+                        if let Err(e) = args.control.on_open(port.as_mut(), false) {
+                            eprintln!("{e}");
+                        }
+
+                        marker(&mut output, &format!("Reconnected on {path}"));
+                    }
+                    Err(e) => eprintln!("{:?}", e),
+                }
+
+                while let Ok(command) = commands.try_recv() {
+                    match command {
+                        // An empty line would be an empty command
+                        ConsoleCommand::Send(data) if data.is_empty() => {}
+                        ConsoleCommand::Send(data) => {
+                            let command = output.commands.next(&String::from_utf8_lossy(&data));
+                            let packet = match command.to_packet_version(COMMAND_VERSION) {
+                                Ok(packet) => packet,
+                                Err(e) => {
+                                    eprintln!("{e}");
+                                    continue;
+                                }
+                            };
+                            match port.write_all(&packet) {
+                                Ok(()) => {
+                                    output.sent(&command, Local::now());
+                                    if let Some(capture) = &capture {
+                                        capture.record(RecordKind::Sent, &packet);
+                                    }
+                                }
+                                Err(e) => {
+                                    eprintln!("Failed to send to \"{port_path}\". Error: {e}")
+                                }
+                            }
+                        }
+                        ConsoleCommand::Quit => break 'capture,
+                        command => match args.control.run(port.as_mut(), command) {
+                            Ok(message) => marker(&mut output, &message),
+                            Err(e) => eprintln!("{e}"),
+                        },
+                    }
+                }
+            }
+
+            output.summary(&decoder);
+            Ok(())
+        }
+        Err(e) => {
+            eprintln!("Failed to open \"{}\". Error: {}", port_path, e);
+            ::std::process::exit(1);
+        }
+    }
+}
+
+/// Decodes the capture at `path` as if it was received again, without prompting.
+fn replay(args: &TlogArgs, path: &Path) -> Result<(), Box<dyn std::error::Error>> {
+    let reader = CaptureReader::open(path)?;
+    let info = reader.info();
+    println!(
+        "Replaying {} captured on {} at {} baud, {}:",
+        path.display(),
+        info.port,
+        info.baud,
+        info.line
+    );
+
+    let output = match &args.output {
+        Some(output) if !args.no_output => Some(expand_output_name(output)),
+        _ => None,
+    };
+    let mut output = TlogOutput::new(args, output)?;
+    let mut decoder = decoder(args, args.timeout.unwrap_or(5));
+
+    reader.replay(args.realtime, |record| match record.kind {
+        RecordKind::Received => decoder.push(&record.data, record.time, |event| {
+            write_event(event, record.time, &mut output)
+        }),
+        // Every command frame is written, and recorded, on its own
+        RecordKind::Sent => match TLog::from_be_bytes(&record.data) {
+            Ok(command) => output.sent(&command, record.time),
+            Err(e) => eprintln!("Invalid command frame in the capture. {e}"),
+        },
+        RecordKind::Marker => {
+            let message = String::from_utf8_lossy(&record.data);
+            output.marker(marker_line_at(&message, record.time))
+        }
+    })?;
+
+    output.summary(&decoder);
+    Ok(())
+}
+
+fn decoder(args: &TlogArgs, time_out: u64) -> TLogDecoder {
+    TLogDecoder::new(
+        args.length.max_length(MAX_FRAME_LENGTH),
+        args.length.overflow(),
+        Duration::from_secs(time_out),
+    )
+    .encoding(args.encoding.unwrap_or_default())
+}
+
+/// Writes a decoded frame as a line, errors other than invalid frames only show up
+/// in the summary.
+fn write_event(event: TLogEvent, received: DateTime<Local>, output: &mut TlogOutput) {
+    match event {
+        TLogEvent::Frame(tlog) => output.frame(&tlog, received, false),
+        TLogEvent::Truncated(tlog) => output.frame(&tlog, received, true),
+        TLogEvent::Sequence(issue) => {
+            let line = note_line("Sequence", Color::Yellow, &issue.to_string(), received);
+            output.write(PayloadType::Warning, None, line)
+        }
+        TLogEvent::Error(FramingError::Invalid(e @ TLogError::CrcMismatch { .. })) => {
+            let line = note_line("Corrupt", Color::Magenta, &e.to_string(), received);
+            output.write(PayloadType::Error, None, line)
+        }
+        TLogEvent::Error(e @ FramingError::Invalid(_)) => eprintln!("{e}"),
+        TLogEvent::Error(_) => (),
+    }
+}
+
+/// Where lines go, the terminal and the output file or files each only getting the
+/// ones at or above their minimum level, of the channels asked for.
+struct TlogOutput {
+    console: OutputWriter,
+    console_level: Level,
+    file_level: Level,
+    files: Option<OutputFiles>,
+    levels: Vec<TagName>,
+    channel_names: Vec<TagName>,
+    /// Ids or names of the channels shown, all if empty.
+    channels: Vec<String>,
+    clock: DeviceClock,
+    commands: PendingCommands,
+    /// Format strings of deferred payloads, shown as their index and arguments without.
+    formats: Option<FormatTable>,
+    encoding: Encoding,
+}
+
+/// The output file, or a file per channel or level name or both, named like
+/// `run1.radio.error.txt` and opened with their first line. Files opened later start
+/// with the marker lines written so far, like the session header.
+struct OutputFiles {
+    output: String,
+    split_levels: bool,
+    split_channels: bool,
+    /// Writers by the part of their name after the output name.
+    writers: HashMap<String, OutputWriter>,
+    markers: Vec<Vec<u8>>,
+}
+
+impl OutputFiles {
+    fn new(output: String, split_levels: bool, split_channels: bool) -> Self {
+        let mut files = Self {
+            output,
+            split_levels,
+            split_channels,
+            writers: HashMap::new(),
+            markers: Vec::new(),
+        };
+        // A single file is opened right away, so a bad name shows up before capturing
+        if !split_levels && !split_channels {
+            files.writer(String::new());
+        }
+        files
+    }
+
+    fn writer(&mut self, suffix: String) -> &OutputWriter {
+        self.writers.entry(suffix).or_insert_with_key(|suffix| {
+            let name = match suffix.is_empty() {
+                true => self.output.clone(),
+                false => format!("{}.{suffix}", self.output),
+            };
+            let writer = OutputWriter::spawn(Some(open_output("tlog", &name)), false);
+            for line in &self.markers {
+                writer.write(line.clone());
+            }
+            writer
+        })
+    }
+
+    /// Writes `line` to every file, the ones opened later included.
+    fn marker(&mut self, line: &[u8]) {
+        for writer in self.writers.values() {
+            writer.write(line.to_vec());
+        }
+        self.markers.push(line.to_vec());
+    }
+}
+
+impl TlogOutput {
+    fn new(args: &TlogArgs, output: Option<String>) -> Result<Self, String> {
+        let formats = match &args.elf {
+            Some(path) => Some(FormatTable::from_elf(path)?),
+            None => None,
+        };
+        Ok(Self {
+            console: OutputWriter::spawn(None, true),
+            console_level: args.console_level.unwrap_or_default(),
+            file_level: args.file_level.unwrap_or_default(),
+            files: output
+                .map(|output| OutputFiles::new(output, args.split_levels, args.split_channels)),
+            levels: args.levels.clone(),
+            channel_names: args.channel_names.clone(),
+            channels: args.channels.clone(),
+            clock: DeviceClock::new(
+                args.tick_rate.unwrap_or(DEFAULT_TICK_RATE),
+                args.device_clock,
+            ),
+            commands: PendingCommands::default(),
+            formats,
+            encoding: args.encoding.unwrap_or_default(),
+        })
+    }
+
+    /// Writes the end of session summary of `decoder` everywhere.
+    fn summary(&mut self, decoder: &TLogDecoder) {
+        self.marker(marker_line(&decoder.counts().to_string()));
+        if let Some(drift) = self.clock.drift() {
+            self.marker(marker_line(&drift));
+        }
+        if self.commands.sent > 0 {
+            self.marker(marker_line(&self.commands.to_string()));
+        }
+    }
+
+    /// Writes the line showing `command`, sent to the device at `sent`, and waits for
+    /// its reply. The line is written everywhere like a marker, whatever the levels
+    /// shown.
+    fn sent(&mut self, command: &TLog, sent: DateTime<Local>) {
+        let text = match command.payload() {
+            Payload::Text(text) => text.clone(),
+            Payload::Deferred { index, args } => deferred_text(*index, args, None),
+        };
+        let sequence = command.sequence.unwrap_or(0);
+        let line = note_line(&format!("TX #{sequence}"), Color::Magenta, &text, sent);
+        self.commands.sent(sequence, text, sent);
+        self.marker(line);
+    }
+
+    /// Writes the line showing `tlog`, received at `received`. Frames without a channel
+    /// count as channel 0.
+    fn frame(&mut self, tlog: &TLog, received: DateTime<Local>, truncated: bool) {
+        // Replies on hidden channels still answer their command
+        let answered =
+            (tlog.kind == FrameKind::Reply).then(|| self.commands.reply(tlog.sequence, received));
+        let channel = tlog.channel.unwrap_or(0);
+        if !self.shows_channel(channel) {
+            return;
+        }
+
+        // The CRC of a truncated frame was discarded, so its ticks may be corrupt and
+        // would throw off the clock
+        let device_time = match (tlog.ticks, truncated) {
+            (Some(ticks), false) => Some(self.clock.observe(ticks, received)),
+            _ => None,
+        };
+        let mut tags = String::new();
+        if tlog.channel.is_some() {
+            let (name, color) = channel_name(channel, &self.channel_names);
+            tags = format!("\x1b[0m{}[{name}]\x1b[0m ", color.ansi());
+        }
+        tags.push_str(&level_tag(tlog.payload_type, &self.levels));
+        if let Some(answered) = answered {
+            tags.push_str(&format!("\x1b[90m({answered})\x1b[0m "));
+        }
+
+        let text = match tlog.payload() {
+            Payload::Text(text) => Cow::Borrowed(text.as_str()),
+            Payload::Deferred { index, args } => {
+                Cow::Owned(deferred_text(*index, args, self.formats.as_ref()))
+            }
+        };
+        let mut line = tlog_line(
+            &text,
+            self.encoding,
+            received,
+            device_time.as_deref(),
+            &tags,
+        );
+        if truncated {
+            line = tag_truncated(line);
+        }
+        self.write(tlog.payload_type, Some(channel), line);
+    }
+
+    fn shows_channel(&self, channel: u8) -> bool {
+        if self.channels.is_empty() {
+            return true;
+        }
+        let (name, _) = channel_name(channel, &self.channel_names);
+        self.channels
+            .iter()
+            .any(|wanted| wanted.eq_ignore_ascii_case(&name) || *wanted == channel.to_string())
+    }
+
+    /// Writes `line` showing a frame of `payload_type` from `channel`, or a note about
+    /// the stream without a channel, where its level is wanted.
+    fn write(&mut self, payload_type: PayloadType, channel: Option<u8>, line: Vec<u8>) {
+        let level = payload_type.level();
+        if let (true, Some(files)) = (level >= self.file_level, &mut self.files) {
+            let mut suffix = Vec::new();
+            if let (true, Some(channel)) = (files.split_channels, channel) {
+                suffix.push(file_suffix(&channel_name(channel, &self.channel_names).0));
+            }
+            if files.split_levels {
+                suffix.push(file_suffix(&level_name(payload_type, &self.levels).0));
+            }
+            files.writer(suffix.join(".")).write(line.clone());
+        }
+        if level >= self.console_level {
+            self.console.write(line);
+        }
+    }
+
+    /// Writes a marker line everywhere, see [`OutputFiles::marker`].
+    fn marker(&mut self, line: Vec<u8>) {
+        if let Some(files) = &mut self.files {
+            files.marker(&line);
+        }
+        self.console.write(line);
+    }
+}
+
+/// Commands sent to the device waiting for their reply, by sequence number.
+///
+/// Commands are numbered by the host, and a [`FrameKind::Reply`] frame carries the
+/// number of the command it answers, or answers the oldest command waiting if it has
+/// no number. Each command gets a single reply.
+#[derive(Debug, Default)]
+struct PendingCommands {
+    /// Number of the next command.
+    next: u16,
+    /// Number, text and send time of every command without a reply yet, oldest first.
+    pending: VecDeque<(u16, String, DateTime<Local>)>,
+    sent: u64,
+    answered: u64,
+}
+
+impl PendingCommands {
+    /// A command frame with `text`, numbered after the last one.
+    fn next(&mut self, text: &str) -> TLog {
+        let command = TLog::new(text.to_owned(), PayloadType::Info)
+            .with_sequence(self.next)
+            .with_kind(FrameKind::Command);
+        self.next = self.next.wrapping_add(1);
+        command
+    }
+
+    fn sent(&mut self, sequence: u16, text: String, time: DateTime<Local>) {
+        if self.pending.len() == MAX_PENDING_COMMANDS {
+            self.pending.pop_front();
+        }
+        self.pending.push_back((sequence, text, time));
+        self.sent += 1;
+    }
+
+    /// Which command a reply with `sequence` answers and how long it took, received at
+    /// `received`.
+    fn reply(&mut self, sequence: Option<u16>, received: DateTime<Local>) -> String {
+        let position = match sequence {
+            Some(sequence) => self.pending.iter().position(|(s, ..)| *s == sequence),
+            None => (!self.pending.is_empty()).then_some(0),
+        };
+        let Some((sequence, text, sent)) = position.and_then(|i| self.pending.remove(i)) else {
+            return match sequence {
+                Some(sequence) => format!("reply to unknown command #{sequence}"),
+                None => "reply to no command".to_owned(),
+            };
+        };
+        self.answered += 1;
+        let millis = (received - sent).num_milliseconds();
+        format!("reply to #{sequence} {text:?} after {millis} ms")
+    }
+}
+
+impl Display for PendingCommands {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(
+            f,
+            "Commands sent: {}, answered: {}",
+            self.sent, self.answered
+        )
+    }
+}
+
+/// Turns the tick counts of version 4 frames into device times, and measures how far
+/// the device clock drifts from the host one.
+struct DeviceClock {
+    /// Ticks per second.
+    rate: u64,
+    /// Show device times as wall clock times instead of uptimes.
+    wall_clock: bool,
+    last_ticks: Option<u32>,
+    /// Ticks counted before the last rollover of the 32 bit counter.
+    rollovers: u64,
+    /// Host time and ticks of the first frame since the device started counting.
+    first: Option<(DateTime<Local>, u64)>,
+    /// Host time and ticks of the last frame.
+    last: Option<(DateTime<Local>, u64)>,
+}
+
+impl DeviceClock {
+    fn new(rate: u64, wall_clock: bool) -> Self {
+        Self {
+            rate,
+            wall_clock,
+            last_ticks: None,
+            rollovers: 0,
+            first: None,
+            last: None,
+        }
+    }
+
+    /// The device time of a frame with `ticks` received at `received`. The first
+    /// frame maps its ticks to its host time, later ones are placed relative to it.
+    fn observe(&mut self, ticks: u32, received: DateTime<Local>) -> String {
+        if let Some(last_ticks) = self.last_ticks {
+            if ticks < last_ticks {
+                // A counter about to roll over that starts again rolled over, any other
+                // step back is the device starting to count again
+                if last_ticks > 3 << 30 && ticks < 1 << 30 {
+                    self.rollovers += 1 << 32;
+                } else {
+                    self.rollovers = 0;
+                    self.first = None;
+                }
+            }
+        }
+        self.last_ticks = Some(ticks);
+        let total = self.rollovers + u64::from(ticks);
+        let (first_time, first_ticks) = *self.first.get_or_insert((received, total));
+        self.last = Some((received, total));
+
+        if self.wall_clock {
+            let elapsed = self.micros(total - first_ticks);
+            let time = first_time + chrono::Duration::microseconds(elapsed as i64);
+            time.format("%H:%M:%S%.6f").to_string()
+        } else {
+            let micros = self.micros(total);
+            format!("{}.{:06}", micros / 1_000_000, micros % 1_000_000)
+        }
+    }
+
+    fn micros(&self, ticks: u64) -> u64 {
+        (u128::from(ticks) * 1_000_000 / u128::from(self.rate)) as u64
+    }
+
+    /// How much faster the device clock ran than the host one from the first frame
+    /// with ticks to the last.
+    fn drift(&self) -> Option<String> {
+        let ((first_time, first_ticks), (last_time, last_ticks)) = (self.first?, self.last?);
+        let host = (last_time - first_time).num_microseconds()?;
+        if host <= 0 {
+            return None;
+        }
+        let device = self.micros(last_ticks - first_ticks) as i64;
+        let drift = device - host;
+        Some(format!(
+            "Device clock drift: {:+.3} ms over {:.1} s ({:+.1} ppm)",
+            drift as f64 / 1e3,
+            host as f64 / 1e6,
+            drift as f64 * 1e6 / host as f64
+        ))
+    }
+}
+
+/// A line about the stream rather than a frame, like a frame that failed its CRC
+/// check, with a tag like the ones of frames.
+fn note_line(tag: &str, color: Color, message: &str, time: DateTime<Local>) -> Vec<u8> {
+    let mut data = format_timestamp(time).into_bytes();
+    data.extend_from_slice(format!("\x1b[0m{}[{tag}]\x1b[0m {message}\n", color.ansi()).as_bytes());
+    data
+}
+
+/// The text of a deferred payload, or its format string index and arguments in hex if
+/// it can't be formatted.
+fn deferred_text(index: u16, args: &[u8], formats: Option<&FormatTable>) -> String {
+    let hex = args
+        .iter()
+        .map(|byte| format!("{byte:02x}"))
+        .collect::<Vec<_>>()
+        .join(" ");
+    match formats.map(|formats| formats.render(index, args)) {
+        Some(Ok(text)) => text,
+        Some(Err(e)) => format!("<fmt #{index}: {e}> {hex}"),
+        None => format!("<fmt #{index}> {hex}"),
+    }
+}
+
+/// A line showing the text of a frame in `encoding`, received at `time`, with `tags`
+/// like the colored tag of its payload type and the device time if it has one. Every
+/// row of a hex dump starts with all of them.
+fn tlog_line(
+    text: &str,
+    encoding: Encoding,
+    time: DateTime<Local>,
+    device_time: Option<&str>,
+    tags: &str,
+) -> Vec<u8> {
+    let timestamp = format_timestamp(time).into_bytes();
+    let device_time = device_time.map_or(String::new(), |device_time| {
+        format!("\x1b[0m\x1b[90m<{device_time}>\x1b[0m ")
+    });
+
+    let colored_message = tags;
+    let rows = encoding.rows(text.as_bytes());
+
+    // Less resizing when using with_capacity
+    let mut data = Vec::with_capacity(
+        (timestamp.len() + device_time.len() + 1 + colored_message.len()) * rows.len() + text.len(),
+    );
+
+    for row in rows {
+        data.extend_from_slice(&timestamp);
+        data.extend_from_slice(device_time.as_bytes());
+        data.extend_from_slice(colored_message.as_bytes());
+        data.extend_from_slice(row);
+        data.extend_from_slice(String::from("\n").as_bytes());
+    }
+    data
+}
+
+/// Decodes `frame`, the first bytes of a frame over the maximum length, with its
+/// payload cut short. `frame` is at least as long as the header and prefix of its
+/// version. Its CRC, if any, is in the discarded part so it isn't checked.
+fn truncated_frame(frame: &[u8], encoding: Encoding) -> Result<TLog, TLogError> {
+    let (version, flags) =
+        FrameVersion::split_byte(frame[4]).ok_or(TLogError::BadVersion(frame[4]))?;
+    let prefix_length = version.prefix_length(frame[HEADER_LENGTH]);
+    let (prefix, mut payload) = frame[HEADER_LENGTH..].split_at(prefix_length);
+    // The rest of a character cut in half is in the discarded part, a deferred payload
+    // just misses arguments and the other encodings show every byte on its own
+    let utf8 = matches!(encoding, Encoding::Utf8 | Encoding::Lossy);
+    if let (0, true, Err(e)) = (flags & flags::DEFERRED, utf8, std::str::from_utf8(payload)) {
+        if e.error_len().is_none() {
+            payload = &payload[..e.valid_up_to()];
+        }
+    }
+    TLog::from_parts(frame[3], version, flags, prefix, payload, encoding)
+}
+
+fn get_time_out() -> Option<u64> {
+    loop {
+        match CustomType::new("What is the timeout in seconds:")
+            .with_error_message("Please type a valid number")
+            .with_help_message("esc for default")
+            .prompt_skippable()
+        {
+            Ok(ans) => break Some(ans.unwrap_or(5)),
+            Err(InquireError::OperationInterrupted) => break None,
+            Err(_) => eprintln!("{}", "Please type a correct value".red().slow_blink()),
+        }
+    }
+}
+
+/// Something [`TLogDecoder`] found in the received bytes.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum TLogEvent {
+    /// A complete frame.
+    Frame(TLog),
+    /// The start of a frame over the maximum length, the rest of it is discarded.
+    Truncated(TLog),
+    /// A break in the sequence numbers, reported before the frame it was found at.
+    Sequence(SequenceIssue),
+    /// Bytes that aren't a frame, or a frame that can't be decoded.
+    Error(FramingError),
+}
+
+/// A break between the sequence numbers of two frames in a row.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum SequenceIssue {
+    /// The frames from `expected` up to before `received` were lost.
+    Gap { expected: u16, received: u16 },
+    /// The last frame came again.
+    Duplicate(u16),
+    /// The numbers went back, usually because the device restarted.
+    Reset { expected: u16, received: u16 },
+}
+
+impl Display for SequenceIssue {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::Gap { expected, received } => write!(
+                f,
+                "{} frames lost, expected {expected} but got {received}",
+                received.wrapping_sub(*expected)
+            ),
+            Self::Duplicate(sequence) => write!(f, "Frame {sequence} received again"),
+            Self::Reset { expected, received } => {
+                write!(f, "Sequence restarted at {received}, expected {expected}")
+            }
+        }
+    }
+}
+
+/// Why [`TLogDecoder`] passed over received bytes.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum FramingError {
+    /// This many bytes were skipped looking for the start of a frame, reported once
+    /// per chunk.
+    Garbage(usize),
+    /// A frame of this length, over the maximum length, was discarded as a whole.
+    Dropped(usize),
+    /// The rest of a frame didn't arrive within the timeout.
+    TimedOut,
+    /// A frame with a valid header whose payload couldn't be decoded.
+    Invalid(TLogError),
+}
+
+impl Display for FramingError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::Garbage(len) => write!(f, "Skipped {len} bytes outside frames"),
+            Self::Dropped(len) => write!(f, "Dropped a frame of {len} bytes"),
+            Self::TimedOut => write!(f, "Incomplete frame timed out"),
+            Self::Invalid(e) => write!(f, "Error parsing TLog: {e}"),
+        }
+    }
+}
+
+/// What a [`TLogDecoder`] has decoded and passed over so far.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub struct TLogCounts {
+    pub frames: u64,
+    pub overflow: OverflowCounts,
+    pub invalid: u64,
+    pub crc_failures: u64,
+    pub timed_out: u64,
+    pub garbage: u64,
+    /// Frames missing from gaps in the sequence numbers.
+    pub lost: u64,
+    pub duplicates: u64,
+    pub resets: u64,
+}
+
+impl Display for TLogCounts {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(
+            f,
+            "Frames over the maximum length: {}, invalid frames: {}, CRC failures: {}, incomplete frames timed out: {}, bytes outside frames: {}, \
+             frames lost: {}, duplicate frames: {}, sequence resets: {}",
+            self.overflow,
+            self.invalid,
+            self.crc_failures,
+            self.timed_out,
+            self.garbage,
+            self.lost,
+            self.duplicates,
+            self.resets
+        )
+    }
+}
+
+/// Finds TLog frames in bytes received in chunks of any size, from a port, a
+/// replayed capture or anything else carrying the stream.
+///
+/// Resynchronisation works as follows:
+/// - bytes before a `0x1A` marker are skipped as garbage;
+/// - a marker whose header has an unknown version is garbage too, the search goes on
+///   at the next byte;
+/// - a frame with a valid header is consumed as a whole, even if its payload can't be
+///   decoded, unless it fails its CRC check, then the search goes on after its marker;
+/// - a frame over the maximum length is truncated or dropped as the [`Overflow`]
+///   policy says, unless its version has a CRC that can't be checked on a part of it,
+///   then its marker is garbage and the search goes on at the next byte;
+/// - if the rest of a frame doesn't arrive within the timeout, its marker is skipped
+///   and the bytes after it are searched again.
+pub struct TLogDecoder {
+    max_length: usize,
+    overflow: Overflow,
+    encoding: Encoding,
+    time_out: Duration,
+    pending: Vec<u8>,
+    /// Bytes of a frame over the maximum length still to be discarded.
+    skip: usize,
+    /// When the frame waited on started to arrive, if any.
+    started: Option<DateTime<Local>>,
+    /// Sequence number of the last frame that had one.
+    last_sequence: Option<u16>,
+    counts: TLogCounts,
+}
+
+impl TLogDecoder {
+    /// `max_length` is clamped between the longest header and prefix and the longest
+    /// frame the length field allows.
+    pub fn new(max_length: usize, overflow: Overflow, time_out: Duration) -> Self {
+        Self {
+            max_length: max_length.clamp(HEADER_LENGTH + MAX_PREFIX_LENGTH, MAX_FRAME_LENGTH),
+            overflow,
+            encoding: Encoding::default(),
+            time_out,
+            pending: Vec::new(),
+            skip: 0,
+            started: None,
+            last_sequence: None,
+            counts: TLogCounts::default(),
+        }
+    }
+
+    /// Turns payloads into text using `encoding` instead of strict UTF-8.
+    pub fn encoding(mut self, encoding: Encoding) -> Self {
+        self.encoding = encoding;
+        self
+    }
+
+    /// Decodes `chunk`, received at `received`, calling `emit` with every frame and
+    /// error found. A frame that isn't complete yet is kept for the next chunk.
+    pub fn push(
+        &mut self,
+        chunk: &[u8],
+        received: DateTime<Local>,
+        mut emit: impl FnMut(TLogEvent),
+    ) {
+        // Bytes arriving after the timeout don't belong to the frame waited on
+        self.check_timeout(received, &mut emit);
+        self.pending.extend_from_slice(chunk);
+        self.decode(received, &mut emit);
+    }
+
+    /// Gives up on the frame waited on if it started to arrive longer than the
+    /// timeout before `now`, and searches the bytes after its marker again.
+    pub fn check_timeout(&mut self, now: DateTime<Local>, mut emit: impl FnMut(TLogEvent)) {
+        let expired = self
+            .started
+            .and_then(|started| (now - started).to_std().ok())
+            .is_some_and(|elapsed| elapsed > self.time_out);
+        if !expired {
+            return;
+        }
+
+        self.counts.timed_out += 1;
+        emit(TLogEvent::Error(FramingError::TimedOut));
+        if self.skip > 0 {
+            self.skip = 0;
+        } else {
+            self.pending.drain(..1);
+        }
+        self.started = None;
+        self.decode(now, &mut emit);
+    }
+
+    pub fn counts(&self) -> TLogCounts {
+        self.counts
+    }
+
+    fn decode(&mut self, received: DateTime<Local>, emit: &mut impl FnMut(TLogEvent)) {
+        // Everything before `consumed` is either decoded or can't start a frame.
+        let mut consumed = 0;
+        let mut garbage = 0;
+        let waiting = loop {
+            // Discard what is left of a frame over the maximum length first
+            let discarded = self.skip.min(self.pending.len() - consumed);
+            consumed += discarded;
+            self.skip -= discarded;
+            if self.skip > 0 {
+                break true;
+            }
+
+            let Some(offset) = memchr(MARKER, &self.pending[consumed..]) else {
+                garbage += self.pending.len() - consumed;
+                consumed = self.pending.len();
+                break false;
+            };
+            garbage += offset;
+            let start = consumed + offset;
+            consumed = start;
+
+            if self.pending.len() < start + HEADER_LENGTH {
+                break true; // Wait for the rest of the header
+            }
+            let Some((version, _)) = FrameVersion::split_byte(self.pending[start + 4]) else {
+                // A marker byte among garbage rather than the start of a frame
+                garbage += 1;
+                consumed += 1;
+                continue;
+            };
+            let fields_byte = match (version.fields(), self.pending.get(start + HEADER_LENGTH)) {
+                (Some(_), _) => 0,
+                (None, None) => break true, // Wait for the fields of a version 5 frame
+                (None, Some(&byte)) if byte & !fields::ALL != 0 => {
+                    garbage += 1;
+                    consumed += 1;
+                    continue;
+                }
+                (None, Some(&byte)) => byte,
+            };
+
+            let len_bytes = [self.pending[start + 1], self.pending[start + 2]];
+            let frame_len = u16::from_be_bytes(len_bytes) as usize
+                + HEADER_LENGTH
+                + version.prefix_length(fields_byte)
+                + version.trailer_length();
+            if frame_len > self.max_length && version.trailer_length() == CRC_LENGTH {
+                // Likely a false marker, which would swallow the frames after it
+                garbage += 1;
+                consumed += 1;
+                continue;
+            }
+            if garbage > 0 {
+                self.skipped(garbage, emit);
+                garbage = 0;
+            }
+
+            if frame_len > self.max_length {
+                if self.overflow == Overflow::Drop {
+                    self.counts.overflow.dropped += 1;
+                    emit(TLogEvent::Error(FramingError::Dropped(frame_len)));
+                    self.skip = frame_len;
+                    continue;
+                }
+                // Decode the start of the frame once it is in and discard the rest
+                let end = start + self.max_length;
+                if self.pending.len() < end {
+                    break true;
+                }
+                match truncated_frame(&self.pending[start..end], self.encoding) {
+                    Ok(tlog) => {
+                        self.check_sequence(&tlog, emit);
+                        emit(TLogEvent::Truncated(tlog))
+                    }
+                    Err(e) => self.invalid(e, emit),
+                }
+                self.counts.overflow.truncated += 1;
+                consumed = end;
+                self.skip = frame_len - self.max_length;
+                continue;
+            }
+
+            let end = start + frame_len;
+            if self.pending.len() < end {
+                break true; // Wait for the rest of the frame
+            }
+            match TLog::decode(&self.pending[start..end], self.encoding) {
+                Ok(tlog) => {
+                    self.counts.frames += 1;
+                    self.check_sequence(&tlog, emit);
+                    emit(TLogEvent::Frame(tlog));
+                }
+                Err(e @ TLogError::CrcMismatch { .. }) => {
+                    // Either a corrupt frame or a false marker, so the bytes after
+                    // the marker may hold frames
+                    self.counts.crc_failures += 1;
+                    emit(TLogEvent::Error(FramingError::Invalid(e)));
+                    consumed = start + 1;
+                    continue;
+                }
+                Err(e) => self.invalid(e, emit),
+            }
+            consumed = end;
+        };
+        if garbage > 0 {
+            self.skipped(garbage, emit);
+        }
+        self.pending.drain(..consumed);
+
+        self.started = match waiting {
+            false => None,
+            // The frame waited on is a new one, or more of the one being discarded
+            true if consumed > 0 => Some(received),
+            true => self.started.or(Some(received)),
+        };
+    }
+
+    /// Reports a break between the sequence numbers of the last numbered frame and
+    /// `tlog`. Frames without a number are left out, like replies numbered after the
+    /// command they answer.
+    fn check_sequence(&mut self, tlog: &TLog, emit: &mut impl FnMut(TLogEvent)) {
+        let (Some(received), false) = (tlog.sequence, tlog.kind == FrameKind::Reply) else {
+            return;
+        };
+        let last = self.last_sequence.replace(received);
+        let Some(last) = last else {
+            return;
+        };
+
+        let expected = last.wrapping_add(1);
+        let ahead = received.wrapping_sub(expected);
+        let issue = match received {
+            _ if ahead == 0 => return,
+            _ if received == last => {
+                self.counts.duplicates += 1;
+                SequenceIssue::Duplicate(received)
+            }
+            // Half of the numbers ahead count as a gap, the other half as going back
+            _ if received != 0 && ahead < 0x8000 => {
+                self.counts.lost += u64::from(ahead);
+                SequenceIssue::Gap { expected, received }
+            }
+            _ => {
+                self.counts.resets += 1;
+                SequenceIssue::Reset { expected, received }
+            }
+        };
+        emit(TLogEvent::Sequence(issue));
+    }
+
+    fn skipped(&mut self, garbage: usize, emit: &mut impl FnMut(TLogEvent)) {
+        self.counts.garbage += garbage as u64;
+        emit(TLogEvent::Error(FramingError::Garbage(garbage)));
+    }
+
+    fn invalid(&mut self, e: TLogError, emit: &mut impl FnMut(TLogEvent)) {
+        self.counts.invalid += 1;
+        emit(TLogEvent::Error(FramingError::Invalid(e)));
+    }
+}
+
+/// Severity of a frame, the fourth byte of its header. The bytes of Debug, Warning
+/// and Error come first for devices only sending those.
+#[derive(Debug, PartialEq, Eq, Clone, Copy, Hash)]
+pub enum PayloadType {
+    Trace,
+    Debug,
+    Info,
+    Warning,
+    Error,
+    Fatal,
+    /// A byte without a standard level, shown as set with `--level` if it is.
+    Unknown(u8),
+}
+
+impl PayloadType {
+    /// The types with a standard level and their byte, as sent by [`tlog_frame`].
+    const BYTES: [(Self, u8); 6] = [
+        (Self::Debug, payload_type::DEBUG),
+        (Self::Warning, payload_type::WARNING),
+        (Self::Error, payload_type::ERROR),
+        (Self::Trace, payload_type::TRACE),
+        (Self::Info, payload_type::INFO),
+        (Self::Fatal, payload_type::FATAL),
+    ];
+
+    pub fn from_byte(byte: u8) -> Self {
+        Self::BYTES
+            .into_iter()
+            .find(|&(_, known)| known == byte)
+            .map_or(Self::Unknown(byte), |(payload_type, _)| payload_type)
+    }
+
+    pub fn to_byte(self) -> u8 {
+        match self {
+            Self::Unknown(byte) => byte,
+            known => Self::BYTES
+                .into_iter()
+                .find(|&(payload_type, _)| payload_type == known)
+                .map(|(_, byte)| byte)
+                .expect("every type with a level has a byte"),
+        }
+    }
+
+    /// Severity the type is filtered by, Info for types without a standard level.
+    pub fn level(self) -> Level {
+        match self {
+            Self::Trace => Level::Trace,
+            Self::Debug => Level::Debug,
+            Self::Info | Self::Unknown(_) => Level::Info,
+            Self::Warning => Level::Warning,
+            Self::Error => Level::Error,
+            Self::Fatal => Level::Fatal,
+        }
+    }
+
+    /// Name and color shown for the type when it isn't set with `--level`.
+    fn default_tag(self) -> (String, Color) {
+        let (name, color) = match self {
+            Self::Trace => ("Trace", Color::Gray),
+            Self::Debug => ("Debug", Color::Cyan),
+            Self::Info => ("Info", Color::Green),
+            Self::Warning => ("Warning", Color::Yellow),
+            Self::Error => ("Error", Color::Red),
+            Self::Fatal => ("Fatal", Color::BrightRed),
+            Self::Unknown(byte) => return (format!("Unknown {byte}"), Color::White),
+        };
+        (name.to_owned(), color)
+    }
+}
+
+/// Whether a frame is logged by the device or part of a command exchange, flagged
+/// in its version byte so every type byte stays a level.
+#[derive(Debug, PartialEq, Eq, Clone, Copy, Hash, Default)]
+pub enum FrameKind {
+    #[default]
+    Log,
+    /// A command sent by the host to the device, see [`TlogArgs::terminal`].
+    Command,
+    /// The answer of the device to a command, numbered like the command it answers.
+    Reply,
+}
+
+impl FrameKind {
+    fn from_flags(flags: u8) -> Self {
+        match flags {
+            _ if flags & flags::COMMAND != 0 => Self::Command,
+            _ if flags & flags::REPLY != 0 => Self::Reply,
+            _ => Self::Log,
+        }
+    }
+
+    fn to_flags(self) -> u8 {
+        match self {
+            Self::Log => 0,
+            Self::Command => flags::COMMAND,
+            Self::Reply => flags::REPLY,
+        }
+    }
+}
+
+/// Severities frames are filtered by, from the least severe.
+#[derive(
+    Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Default, ValueEnum, Serialize, Deserialize,
+)]
+#[serde(rename_all = "lowercase")]
+pub enum Level {
+    #[default]
+    Trace,
+    Debug,
+    Info,
+    Warning,
+    Error,
+    Fatal,
+}
 
-use crate::utils::generate_timestamp;
+/// Colors a payload type can be shown in.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, ValueEnum)]
+pub enum Color {
+    Black,
+    Red,
+    Green,
+    Yellow,
+    Blue,
+    Magenta,
+    Cyan,
+    White,
+    Gray,
+    BrightRed,
+}
 
-pub fn tlog_main(init: bool) -> Result<(), Box<dyn std::error::Error>> {
-    let options = available_ports().expect("Failed to detect ports");
-    if options.is_empty() {
-        if init {
-            eprintln!("Waiting for serial interfaces...");
+impl Color {
+    fn ansi(self) -> &'static str {
+        match self {
+            Self::Black => "\x1b[30m",
+            Self::Red => "\x1b[31m",
+            Self::Green => "\x1b[32m",
+            Self::Yellow => "\x1b[33m",
+            Self::Blue => "\x1b[34m",
+            Self::Magenta => "\x1b[35m",
+            Self::Cyan => "\x1b[36m",
+            Self::White => "\x1b[37m",
+            Self::Gray => "\x1b[90m",
+            Self::BrightRed => "\x1b[1;91m",
         }
-        thread::sleep(Duration::from_millis(100));
-        return tlog_main(false);
     }
+}
 
-    let port_path = match Select::new(
-        "Select the port to read from:",
-        options
-            .clone()
-            .into_iter()
-            .map(|o| {
-                if o.port_name.starts_with("/sys/class/tty/") {
-                    o.port_name.replace("/sys/class/tty/", "/dev/")
-                } else {
-                    o.port_name
-                }
-            })
-            .collect(),
-    )
-    .prompt()
-    {
-        Ok(k) => k,
-        Err(InquireError::OperationInterrupted) => return Ok(()),
-        Err(_) => return tlog_main(true), // Restarts to check for more iterfaces.
-    };
+/// Name and color shown for a byte of a frame, like its payload type or channel,
+/// written `BYTE=NAME[:COLOR]` like `6=Notice:blue`. Without a color the usual one
+/// for the byte is kept.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(try_from = "String", into = "String")]
+pub struct TagName {
+    source: String,
+    byte: u8,
+    name: String,
+    color: Option<Color>,
+}
 
-    let baud = loop {
-        match CustomType::new("What is the baud rate:")
-            .with_error_message("Please type a valid number")
-            .with_help_message("esc for default")
-            .prompt_skippable()
-        {
-            Ok(ans) => break ans,
-            Err(InquireError::OperationInterrupted) => return Ok(()),
-            Err(_) => eprintln!("{}", "Please type a correct value".red().slow_blink()),
+impl FromStr for TagName {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let source = s.trim();
+        let Some((byte, rest)) = source.split_once('=') else {
+            return Err(format!(
+                "Invalid name \"{source}\", expected BYTE=NAME[:COLOR]"
+            ));
+        };
+        let byte = byte.trim();
+        let byte = match byte.strip_prefix("0x") {
+            Some(hex) => u8::from_str_radix(hex, 16),
+            None => byte.parse(),
         }
+        .map_err(|_| format!("Invalid byte \"{byte}\", expected 0 to 255"))?;
+
+        let (name, color) = match rest.rsplit_once(':') {
+            Some((name, color)) => (name, Some(color)),
+            None => (rest, None),
+        };
+        let color = color
+            .map(|color| {
+                Color::from_str(color.trim(), true)
+                    .map_err(|_| format!("Invalid color \"{color}\""))
+            })
+            .transpose()?;
+
+        Ok(Self {
+            source: source.to_owned(),
+            byte,
+            name: name.trim().to_owned(),
+            color,
+        })
     }
-    .unwrap_or(115200);
+}
 
-    let time_out = loop {
-        match CustomType::new("What is the timeout in seconds:")
-            .with_error_message("Please type a valid number")
-            .with_help_message("esc for default")
-            .prompt_skippable()
-        {
-            Ok(ans) => break ans,
-            Err(InquireError::OperationInterrupted) => return Ok(()),
-            Err(_) => eprintln!("{}", "Please type a correct value".red().slow_blink()),
-        }
+impl TryFrom<String> for TagName {
+    type Error = String;
+
+    fn try_from(value: String) -> Result<Self, Self::Error> {
+        value.parse()
     }
-    .unwrap_or(5);
+}
 
-    let output: Option<String> = loop {
-        match CustomType::new("What is the output file name?:")
-            .with_error_message("Please type a valid file name")
-            .with_help_message("esc to skip outputing to a file")
-            .prompt_skippable()
-        {
-            Ok(ans) => break ans,
-            Err(InquireError::OperationInterrupted) => return Ok(()),
-            Err(_) => eprintln!("{}", "Please type a correct value".red().slow_blink()),
-        }
-    };
+impl From<TagName> for String {
+    fn from(value: TagName) -> Self {
+        value.source
+    }
+}
 
-    match serialport::new(&port_path, baud).open() {
-        Ok(mut port) => {
-            let mut serial_buf = [0; 1];
-            let mut accumulated_data = Vec::new();
-
-            // Introduce the timestamp variable
-            let mut last_packet_detected: Option<Instant> = None;
-            let time_out_duration: Duration = Duration::from_secs(time_out);
-
-            loop {
-                match port.read_exact(&mut serial_buf) {
-                    Ok(_) => {
-                        accumulated_data.extend_from_slice(&serial_buf[..]);
-
-                        while let Some(start_pos) = accumulated_data.iter().position(|&x| x == 0x1A)
-                        {
-                            // Check if we have at least the first 5 bytes (0x1A, len1, len2, 0x1, type)
-                            if accumulated_data.len() > start_pos + 4 {
-                                // Update the timestamp every time we detect the start of a packet
-                                last_packet_detected = Some(Instant::now());
-
-                                let len_bytes = [
-                                    accumulated_data[start_pos + 1],
-                                    accumulated_data[start_pos + 2],
-                                ];
-                                let payload_len = u16::from_be_bytes(len_bytes) as usize;
-
-                                // Ensure we have all bytes of the message
-                                if accumulated_data.len() >= start_pos + payload_len + 5 {
-                                    let data_packet = accumulated_data
-                                        .drain(start_pos..start_pos + payload_len + 5)
-                                        .collect::<Vec<u8>>();
-                                    match TLog::from_be_bytes(data_packet) {
-                                        Ok(tlog) => {
-                                            let timestamp = generate_timestamp().into_bytes();
-
-                                            let colored_message = match tlog.payload_type {
-                                                PayloadType::Debug => {
-                                                    "\x1b[0m\x1b[36m[Debug]\x1b[0m ".to_string()
-                                                } // Cyan color for Debug
-                                                PayloadType::Warning => {
-                                                    "\x1b[0m\x1b[33m[Warning]\x1b[0m ".to_string()
-                                                } // Yellow color for Warning
-                                                PayloadType::Error => {
-                                                    "\x1b[0m\x1b[31m[Error]\x1b[0m ".to_string()
-                                                } // Red color for Error
-                                                PayloadType::Unknown => {
-                                                    "\x1b[0m\x1b[37m[Unknown]\x1b[0m ".to_string()
-                                                } // White color for Unknown
-                                            };
-
-                                            // Less resizing when using with_capacity
-                                            let mut data = Vec::with_capacity(
-                                                timestamp.len()
-                                                    + tlog.payload.len()
-                                                    + 1
-                                                    + colored_message.len(),
-                                            );
-
-                                            data.extend_from_slice(&timestamp);
-                                            data.extend_from_slice(colored_message.as_bytes());
-                                            data.extend_from_slice(tlog.payload.as_bytes());
-                                            data.extend_from_slice(String::from("\n").as_bytes());
-
-                                            if let Ok(string) = std::str::from_utf8(&data) {
-                                                print!("{}", string);
-                                            } else {
-                                                eprintln!("Bytes are not valid UTF-8");
-                                            }
-                                            if let Some(ref file) = &output {
-                                                if !Path::new("tlog").exists() {
-                                                    create_dir_all("tlog")
-                                                        .expect("Unable to create dir");
-                                                }
-
-                                                let mut file = match OpenOptions::new()
-                                                    .write(true)
-                                                    .append(true)
-                                                    .create(true)
-                                                    .open(format!("tlog/{file}.txt"))
-                                                {
-                                                    Ok(file) => file,
-                                                    Err(e) => {
-                                                        eprintln!(
-                                                            "Failed to open \"{}\". Error: {}",
-                                                            output.as_ref().unwrap().as_str(),
-                                                            e
-                                                        );
-                                                        ::std::process::exit(1);
-                                                    }
-                                                };
-                                                file.write_all(&data).unwrap();
-                                                file.flush().unwrap();
-                                            }
-                                        }
-                                        Err(e) => eprintln!("Error parsing TLog: {}", e),
-                                    }
-                                } else {
-                                    break; // Wait for more data
-                                }
-                            } else {
-                                break; // Wait for more data
-                            }
-                        }
+/// Name and color of `payload_type`, taken from the last of `levels` for its byte
+/// if any.
+fn level_name(payload_type: PayloadType, levels: &[TagName]) -> (String, Color) {
+    let byte = payload_type.to_byte();
+    let (name, color) = payload_type.default_tag();
+    match levels.iter().rev().find(|level| level.byte == byte) {
+        Some(level) => (level.name.clone(), level.color.unwrap_or(color)),
+        None => (name, color),
+    }
+}
 
-                        // Check for timeout
-                        if let Some(timestamp) = last_packet_detected {
-                            if timestamp.elapsed() > time_out_duration {
-                                // Drain accumulated_data up to the detected start position
-                                if let Some(start_pos) =
-                                    accumulated_data.iter().position(|&x| x == 0x1A)
-                                {
-                                    accumulated_data.drain(..=start_pos);
-                                    last_packet_detected = None; // Reset timestamp
-                                }
-                            }
-                        }
-                    }
-                    Err(ref e) if e.kind() == io::ErrorKind::TimedOut => (),
-                    Err(ref e) if e.kind() == io::ErrorKind::BrokenPipe => return tlog_main(true), // Restart
-                    Err(e) => eprintln!("{:?}", e),
-                }
+/// The colored tag shown in front of a payload of `payload_type`.
+fn level_tag(payload_type: PayloadType, levels: &[TagName]) -> String {
+    let (name, color) = level_name(payload_type, levels);
+    format!("\x1b[0m{}[{name}]\x1b[0m ", color.ansi())
+}
 
-                // Here, you can handle the accumulated tlogs if necessary.
-            }
-        }
-        Err(e) => {
-            eprintln!("Failed to open \"{}\". Error: {}", port_path, e);
-            ::std::process::exit(1);
-        }
+/// Colors of channels without one set, picked by their id.
+const CHANNEL_COLORS: [Color; 6] = [
+    Color::Blue,
+    Color::Green,
+    Color::Magenta,
+    Color::Cyan,
+    Color::Yellow,
+    Color::Red,
+];
+
+/// Name and color of `channel`, taken from the last of `channels` for it if any.
+fn channel_name(channel: u8, channels: &[TagName]) -> (String, Color) {
+    let color = CHANNEL_COLORS[usize::from(channel) % CHANNEL_COLORS.len()];
+    match channels.iter().rev().find(|name| name.byte == channel) {
+        Some(name) => (name.name.clone(), name.color.unwrap_or(color)),
+        None => (format!("ch{channel}"), color),
     }
 }
 
+/// `name` made safe to put in a file name, like `run1.error.txt`.
+fn file_suffix(name: &str) -> String {
+    name.to_lowercase()
+        .chars()
+        .map(|c| if c.is_alphanumeric() { c } else { '-' })
+        .collect()
+}
+
+/// What a frame carries, text or a format string to fill in on the host.
 #[derive(Debug, PartialEq, Eq, Clone, Hash)]
-pub enum PayloadType {
-    Debug = 0,
-    Warning = 1,
-    Error = 2,
-    Unknown,
+pub enum Payload {
+    Text(String),
+    /// The offset of a format string in the firmware's [`FormatTable`] and the big endian
+    /// arguments to fill it in with, sent as the big endian offset followed by the
+    /// arguments in a frame with [`flags::DEFERRED`] set in its version byte.
+    Deferred {
+        index: u16,
+        args: Vec<u8>,
+    },
 }
 
 #[derive(Debug, PartialEq, Eq, Clone, Hash)]
 pub struct TLog {
     payload_type: PayloadType,
-    payload: String,
+    kind: FrameKind,
+    payload: Payload,
+    /// Number of the frame, sent in version 3 frames and later.
+    sequence: Option<u16>,
+    /// Device clock ticks when the frame was sent, in version 4 frames and later.
+    ticks: Option<u32>,
+    /// Subsystem of the device the frame is from, in version 5 frames.
+    channel: Option<u8>,
 }
 
 impl TLog {
     pub fn new(payload: String, payload_type: PayloadType) -> Self {
+        Self::with_payload(Payload::Text(payload), payload_type)
+    }
+
+    /// A frame with the format string at `index` to be filled in with `args`, see
+    /// [`Payload::Deferred`].
+    pub fn deferred(index: u16, args: Vec<u8>, payload_type: PayloadType) -> Self {
+        Self::with_payload(Payload::Deferred { index, args }, payload_type)
+    }
+
+    fn with_payload(payload: Payload, payload_type: PayloadType) -> Self {
         Self {
             payload,
             payload_type,
+            kind: FrameKind::Log,
+            sequence: None,
+            ticks: None,
+            channel: None,
         }
     }
 
-    pub fn to_packet(&self) -> Result<Vec<u8>> {
-        if self.payload.len() > u16::MAX.into() {
-            return Err(anyhow!("Too large packet!"));
-        }
-        let p_type: u8 = match self.payload_type {
-            PayloadType::Debug => 0x0,
-            PayloadType::Warning => 0x1,
-            PayloadType::Error => 0x2,
-            PayloadType::Unknown => return Err(anyhow!("Invalid payload type!")),
-        };
+    /// Numbers the frame, the number is sent in version 3 frames and later only.
+    pub fn with_sequence(mut self, sequence: u16) -> Self {
+        self.sequence = Some(sequence);
+        self
+    }
+
+    pub fn sequence(&self) -> Option<u16> {
+        self.sequence
+    }
+
+    /// Stamps the frame with device clock ticks, sent in version 4 frames and later only.
+    pub fn with_ticks(mut self, ticks: u32) -> Self {
+        self.ticks = Some(ticks);
+        self
+    }
+
+    pub fn ticks(&self) -> Option<u32> {
+        self.ticks
+    }
+
+    /// Sets the channel the frame is from, sent in version 5 frames only. Version 5 only
+    /// sends the fields set.
+    pub fn with_channel(mut self, channel: u8) -> Self {
+        self.channel = Some(channel);
+        self
+    }
+
+    pub fn channel(&self) -> Option<u8> {
+        self.channel
+    }
+
+    pub fn payload_type(&self) -> PayloadType {
+        self.payload_type
+    }
+
+    /// Marks the frame as a command or a reply, see [`FrameKind`].
+    pub fn with_kind(mut self, kind: FrameKind) -> Self {
+        self.kind = kind;
+        self
+    }
+
+    pub fn kind(&self) -> FrameKind {
+        self.kind
+    }
+
+    pub fn payload(&self) -> &Payload {
+        &self.payload
+    }
+
+    /// A version 1 frame, see [`TLog::to_packet_version`].
+    pub fn to_packet(&self) -> Result<Vec<u8>, TLogError> {
+        self.to_packet_version(FrameVersion::V1)
+    }
 
-        let payload_len = (self.payload.len() as u16).to_be_bytes();
-        Ok([
-            vec![0x1A, payload_len[0], payload_len[1], p_type, 0x1],
-            self.payload.as_bytes().to_vec(),
+    /// A `version` frame, encoded like firmware does with [`tlog_frame`].
+    pub fn to_packet_version(&self, version: FrameVersion) -> Result<Vec<u8>, TLogError> {
+        let fields = [
+            (fields::SEQUENCE, self.sequence.is_some()),
+            (fields::TICKS, self.ticks.is_some()),
+            (fields::CHANNEL, self.channel.is_some()),
         ]
-        .concat())
-    }
-
-    pub fn from_be_bytes(data_packet: Vec<u8>) -> Result<Self> {
-        // Check initial conditions
-        if data_packet.is_empty() {
-            return Err(anyhow!("Input is empty"));
-        }
-        if data_packet[0] != 0x1A || data_packet[4] != 0x1 {
-            eprintln!(
-                "Invalid packet: {:?}",
-                data_packet
-                    .iter()
-                    .map(|byte| format!("{:02x}", byte))
-                    .collect::<Vec<String>>()
-                    .join(" ")
-            );
-            return Err(anyhow!(
-                "Invalid or unsupported input format, Byte[0]:{:X}, Byte[3]:{:X}",
-                data_packet[0],
-                data_packet[3]
-            ));
+        .into_iter()
+        .filter(|(_, set)| *set)
+        .fold(0, |fields, (field, _)| fields | field);
+        let header = Header {
+            payload_type: self.payload_type.to_byte(),
+            version,
+            flags: self.kind.to_flags(),
+            fields,
+            // Fields of the version not set are sent as 0, like after a reset
+            sequence: self.sequence.unwrap_or(0),
+            ticks: self.ticks.unwrap_or(0),
+            channel: self.channel.unwrap_or(0),
+        };
+        let mut packet = vec![0; version.frame_length(fields, self.payload_length())];
+        let length = match &self.payload {
+            Payload::Text(text) => header.encode(text.as_bytes(), &mut packet),
+            Payload::Deferred { index, args } => header.encode_deferred(*index, args, &mut packet),
+        }
+        .map_err(|e| match e {
+            EncodeError::TooLong(len) => TLogError::TooLong(len),
+            EncodeError::BufferTooSmall(_) => unreachable!("the packet fits its payload"),
+        })?;
+        packet.truncate(length);
+        Ok(packet)
+    }
+
+    /// Bytes of the payload in a frame.
+    fn payload_length(&self) -> usize {
+        match &self.payload {
+            Payload::Text(text) => text.len(),
+            Payload::Deferred { args, .. } => 2 + args.len(),
         }
+    }
+
+    pub fn from_be_bytes(data_packet: &[u8]) -> Result<Self, TLogError> {
+        Self::decode(data_packet, Encoding::Utf8)
+    }
 
-        let payload_len = u16::from_be_bytes([data_packet[1], data_packet[2]]) as usize;
+    /// Like [`TLog::from_be_bytes`], with the payload turned into text using `encoding`.
+    pub fn decode(data_packet: &[u8], encoding: Encoding) -> Result<Self, TLogError> {
+        let Some(&[marker, len_hi, len_lo, p_type, version]) = data_packet.get(..HEADER_LENGTH)
+        else {
+            return Err(TLogError::Truncated(data_packet.len()));
+        };
+        if marker != MARKER {
+            return Err(TLogError::BadMagic(marker));
+        }
+        let Some((version, flags)) = FrameVersion::split_byte(version) else {
+            return Err(TLogError::BadVersion(version));
+        };
 
-        // Ensure packet size consistency
-        if data_packet.len() != payload_len + 5 {
-            return Err(anyhow!("Inconsistent data packet length"));
+        // A version 5 frame too short for its fields byte fails the length check
+        let fields_byte = data_packet.get(HEADER_LENGTH).copied().unwrap_or(0);
+        let payload_start = HEADER_LENGTH + version.prefix_length(fields_byte);
+        let payload_end = payload_start + u16::from_be_bytes([len_hi, len_lo]) as usize;
+        let expected = payload_end + version.trailer_length();
+        if data_packet.len() != expected {
+            return Err(TLogError::LengthMismatch {
+                expected,
+                actual: data_packet.len(),
+            });
+        }
+        if version.trailer_length() == CRC_LENGTH {
+            let expected =
+                u16::from_be_bytes([data_packet[payload_end], data_packet[payload_end + 1]]);
+            let actual = crc16(&data_packet[..payload_end]);
+            if actual != expected {
+                return Err(TLogError::CrcMismatch { expected, actual });
+            }
         }
 
-        let payload_type = match data_packet[3] {
-            0 => PayloadType::Debug,
-            1 => PayloadType::Warning,
-            2 => PayloadType::Error,
-            _ => PayloadType::Unknown,
+        Self::from_parts(
+            p_type,
+            version,
+            flags,
+            &data_packet[HEADER_LENGTH..payload_start],
+            &data_packet[payload_start..payload_end],
+            encoding,
+        )
+    }
+
+    /// A frame from its type byte, the version and flags of its version byte, the bytes
+    /// between its header and payload and its payload.
+    fn from_parts(
+        p_type: u8,
+        version: FrameVersion,
+        flags: u8,
+        prefix: &[u8],
+        payload: &[u8],
+        encoding: Encoding,
+    ) -> Result<Self, TLogError> {
+        let (fields, mut prefix) = match (version.fields(), prefix) {
+            (Some(fields), prefix) => (fields, prefix),
+            (None, [fields, ..]) if fields & !fields::ALL != 0 => {
+                return Err(TLogError::BadFields(*fields))
+            }
+            (None, [fields, prefix @ ..]) => (*fields, prefix),
+            (None, []) => return Err(TLogError::Truncated(HEADER_LENGTH)),
         };
+        let mut field = |field, length| {
+            let (bytes, rest) = prefix.split_at(if fields & field != 0 { length } else { 0 });
+            prefix = rest;
+            (!bytes.is_empty()).then_some(bytes)
+        };
+        let sequence = field(fields::SEQUENCE, 2).map(|b| u16::from_be_bytes([b[0], b[1]]));
+        let ticks = field(fields::TICKS, 4).map(|b| u32::from_be_bytes([b[0], b[1], b[2], b[3]]));
+        let channel = field(fields::CHANNEL, 1).map(|b| b[0]);
 
-        let payload = std::str::from_utf8(&data_packet[5..(payload_len + 5)])
-            .map_err(|e| anyhow!("UTF8 conversion error: {}", e))?
-            .to_owned();
+        let payload = match (flags & flags::DEFERRED, payload) {
+            (0, text) => Payload::Text(encoding.to_text(text).map_err(TLogError::InvalidUtf8)?),
+            (_, [hi, lo, args @ ..]) => Payload::Deferred {
+                index: u16::from_be_bytes([*hi, *lo]),
+                args: args.to_vec(),
+            },
+            (_, _) => return Err(TLogError::NoFormatIndex),
+        };
 
         Ok(Self {
-            payload_type,
+            payload_type: PayloadType::from_byte(p_type),
+            kind: FrameKind::from_flags(flags),
             payload,
+            sequence,
+            ticks,
+            channel,
         })
     }
 }
 
+/// Why a TLog frame couldn't be decoded or encoded.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum TLogError {
+    /// The frame doesn't start with `0x1A` but with this byte.
+    BadMagic(u8),
+    /// The header ends with this unsupported version.
+    BadVersion(u8),
+    /// Only this many bytes, too few for a header.
+    Truncated(usize),
+    /// The frame length doesn't match the length in its header.
+    LengthMismatch { expected: usize, actual: usize },
+    /// The CRC at the end of a version 2 frame or later doesn't match the one of its content.
+    CrcMismatch { expected: u16, actual: u16 },
+    /// The payload isn't valid UTF-8.
+    InvalidUtf8(Utf8Error),
+    /// A payload of this many bytes doesn't fit the length field.
+    TooLong(usize),
+    /// A deferred payload too short for the index of its format string.
+    NoFormatIndex,
+    /// The prefix of a version 5 frame starts with this byte, flagging unknown fields.
+    BadFields(u8),
+}
+
+impl Display for TLogError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::BadMagic(byte) => write!(f, "Frame starts with {byte:#04x} instead of 0x1a"),
+            Self::BadVersion(version) => write!(f, "Unsupported frame version {version}"),
+            Self::Truncated(len) => write!(f, "Frame of {len} bytes is shorter than a header"),
+            Self::LengthMismatch { expected, actual } => {
+                write!(f, "Frame of {actual} bytes, its header says {expected}")
+            }
+            Self::CrcMismatch { expected, actual } => {
+                write!(f, "CRC is {actual:#06x}, the frame says {expected:#06x}")
+            }
+            Self::InvalidUtf8(e) => write!(f, "Payload is not valid UTF-8: {e}"),
+            Self::TooLong(len) => write!(f, "Payload of {len} bytes is too long for a frame"),
+            Self::NoFormatIndex => write!(f, "Deferred payload is missing its format index"),
+            Self::BadFields(byte) => write!(f, "Unknown fields {byte:#04x} in a version 5 frame"),
+        }
+    }
+}
+
+impl std::error::Error for TLogError {}
+
 #[cfg(test)]
 mod tests {
     use super::*;
+    use proptest::prelude::*;
 
     #[test]
     fn test_ascii() {
@@ -315,7 +1822,7 @@ mod tests {
             PayloadType::Debug,
         );
         let packet = tlog.to_packet().unwrap();
-        let out_log = TLog::from_be_bytes(packet).unwrap();
+        let out_log = TLog::from_be_bytes(&packet).unwrap();
 
         assert_eq!(tlog, out_log);
     }
@@ -327,7 +1834,7 @@ mod tests {
             PayloadType::Debug,
         );
         let packet = tlog.to_packet().unwrap();
-        let out_log = TLog::from_be_bytes(packet).unwrap();
+        let out_log = TLog::from_be_bytes(&packet).unwrap();
 
         assert_eq!(tlog, out_log);
     }
@@ -336,8 +1843,556 @@ mod tests {
     fn test_utf8_icon() {
         let tlog = TLog::new("".to_owned(), PayloadType::Debug);
         let packet = tlog.to_packet().unwrap();
-        let out_log = TLog::from_be_bytes(packet).unwrap();
+        let out_log = TLog::from_be_bytes(&packet).unwrap();
 
         assert_eq!(tlog, out_log);
     }
+
+    fn frame(payload: &str, payload_type: PayloadType) -> Vec<u8> {
+        TLog::new(payload.to_owned(), payload_type)
+            .to_packet()
+            .unwrap()
+    }
+
+    fn decode_chunks(decoder: &mut TLogDecoder, chunks: &[&[u8]]) -> Vec<TLogEvent> {
+        let mut events = Vec::new();
+        for chunk in chunks {
+            decoder.push(chunk, Local::now(), |event| events.push(event));
+        }
+        events
+    }
+
+    fn decoder() -> TLogDecoder {
+        TLogDecoder::new(MAX_FRAME_LENGTH, Overflow::Truncate, Duration::from_secs(5))
+    }
+
+    #[test]
+    fn test_decoder_split_frames() {
+        let packet = frame("boot done", PayloadType::Warning);
+        let chunks: Vec<&[u8]> = packet.chunks(1).collect();
+        let mut decoder = decoder();
+        assert_eq!(
+            decode_chunks(&mut decoder, &chunks),
+            [TLogEvent::Frame(TLog::new(
+                "boot done".to_owned(),
+                PayloadType::Warning
+            ))]
+        );
+        assert_eq!(decoder.counts().frames, 1);
+    }
+
+    #[test]
+    fn test_decoder_back_to_back() {
+        let data = [
+            frame("one", PayloadType::Debug),
+            frame("", PayloadType::Error),
+            frame("three", PayloadType::Warning),
+        ]
+        .concat();
+        let mut decoder = decoder();
+        let events = decode_chunks(&mut decoder, &[&data]);
+        assert_eq!(
+            events,
+            [
+                TLogEvent::Frame(TLog::new("one".to_owned(), PayloadType::Debug)),
+                TLogEvent::Frame(TLog::new("".to_owned(), PayloadType::Error)),
+                TLogEvent::Frame(TLog::new("three".to_owned(), PayloadType::Warning)),
+            ]
+        );
+    }
+
+    #[test]
+    fn test_decoder_garbage() {
+        // A marker followed by a header with an unknown version is garbage too
+        let data = [
+            &b"noise\x1a\x00\x01\x00\x07"[..],
+            &frame("one", PayloadType::Debug),
+            b"\x00\xff",
+            &frame("two", PayloadType::Debug),
+        ]
+        .concat();
+        let mut decoder = decoder();
+        let events = decode_chunks(&mut decoder, &[&data[..8], &data[8..]]);
+        assert_eq!(
+            events,
+            [
+                TLogEvent::Error(FramingError::Garbage(5)),
+                TLogEvent::Error(FramingError::Garbage(5)),
+                TLogEvent::Frame(TLog::new("one".to_owned(), PayloadType::Debug)),
+                TLogEvent::Error(FramingError::Garbage(2)),
+                TLogEvent::Frame(TLog::new("two".to_owned(), PayloadType::Debug)),
+            ]
+        );
+        assert_eq!(decoder.counts().garbage, 12);
+    }
+
+    #[test]
+    fn test_decoder_false_oversized_header() {
+        // With a CRC to check, a header over the maximum length is a false marker
+        let v2 = |payload: &str| {
+            TLog::new(payload.to_owned(), PayloadType::Debug)
+                .to_packet_version(FrameVersion::V2)
+                .unwrap()
+        };
+        let data = [&b"\x1a\xff\xff\x00\x02"[..], &v2("one"), &v2("two")].concat();
+        for overflow in [Overflow::Truncate, Overflow::Drop] {
+            let mut decoder = TLogDecoder::new(64, overflow, Duration::from_secs(5));
+            assert_eq!(
+                decode_chunks(&mut decoder, &[&data[..7], &data[7..]]),
+                [
+                    TLogEvent::Error(FramingError::Garbage(5)),
+                    TLogEvent::Frame(TLog::new("one".to_owned(), PayloadType::Debug)),
+                    TLogEvent::Frame(TLog::new("two".to_owned(), PayloadType::Debug)),
+                ]
+            );
+            assert_eq!(decoder.counts().overflow, OverflowCounts::default());
+        }
+    }
+
+    #[test]
+    fn test_decoder_timeout_resyncs() {
+        // A frame cut short by a reset, the next one starts inside its claimed length
+        let next = frame("after reset", PayloadType::Debug);
+        let data = [&b"\x1a\x00\x40\x00\x01cut"[..], &next].concat();
+        let mut decoder = decoder();
+        let start = Local::now();
+        let mut events = Vec::new();
+        decoder.push(&data, start, |event| events.push(event));
+        assert!(events.is_empty());
+
+        decoder.check_timeout(start + chrono::Duration::seconds(6), |event| {
+            events.push(event)
+        });
+        assert_eq!(
+            events,
+            [
+                TLogEvent::Error(FramingError::TimedOut),
+                TLogEvent::Error(FramingError::Garbage(7)),
+                TLogEvent::Frame(TLog::new("after reset".to_owned(), PayloadType::Debug)),
+            ]
+        );
+    }
+
+    #[test]
+    fn test_decoder_overflow() {
+        let long = frame("0123456789", PayloadType::Debug);
+        let short = frame("ok", PayloadType::Debug);
+        let data = [&long[..], &short].concat();
+
+        // Clamped to the longest header and prefix
+        let mut decoder = TLogDecoder::new(12, Overflow::Truncate, Duration::from_secs(5));
+        assert_eq!(
+            decode_chunks(&mut decoder, &[&data]),
+            [
+                TLogEvent::Truncated(TLog::new("01234567".to_owned(), PayloadType::Debug)),
+                TLogEvent::Frame(TLog::new("ok".to_owned(), PayloadType::Debug)),
+            ]
+        );
+
+        // Only UTF-8 is cut back to the last whole character
+        let long = frame("0123456é", PayloadType::Debug);
+        for (encoding, text) in [(Encoding::Utf8, "0123456"), (Encoding::Latin1, "0123456Ã")] {
+            let mut decoder =
+                TLogDecoder::new(12, Overflow::Truncate, Duration::from_secs(5)).encoding(encoding);
+            assert_eq!(
+                decode_chunks(&mut decoder, &[&long]),
+                [TLogEvent::Truncated(TLog::new(
+                    text.to_owned(),
+                    PayloadType::Debug
+                ))]
+            );
+        }
+
+        let mut decoder = TLogDecoder::new(12, Overflow::Drop, Duration::from_secs(5));
+        assert_eq!(
+            decode_chunks(&mut decoder, &[&data[..4], &data[4..]]),
+            [
+                TLogEvent::Error(FramingError::Dropped(15)),
+                TLogEvent::Frame(TLog::new("ok".to_owned(), PayloadType::Debug)),
+            ]
+        );
+    }
+
+    #[test]
+    fn test_parse_errors() {
+        for len in 0..HEADER_LENGTH {
+            assert_eq!(
+                TLog::from_be_bytes(&[0x1A, 0, 0, 0][..len.min(4)]),
+                Err(TLogError::Truncated(len.min(4)))
+            );
+        }
+        assert_eq!(
+            TLog::from_be_bytes(b"\x1b\x00\x00\x00\x01"),
+            Err(TLogError::BadMagic(0x1B))
+        );
+        assert_eq!(
+            TLog::from_be_bytes(b"\x1a\x00\x00\x00\x07"),
+            Err(TLogError::BadVersion(7))
+        );
+        assert_eq!(
+            TLog::from_be_bytes(b"\x1a\x00\x02\x00\x01a"),
+            Err(TLogError::LengthMismatch {
+                expected: 7,
+                actual: 6
+            })
+        );
+        assert!(matches!(
+            TLog::from_be_bytes(b"\x1a\x00\x01\x00\x01\xff"),
+            Err(TLogError::InvalidUtf8(_))
+        ));
+    }
+
+    #[test]
+    fn test_payload_type_bytes() {
+        for byte in 0..=u8::MAX {
+            assert_eq!(PayloadType::from_byte(byte).to_byte(), byte);
+        }
+        assert_eq!(PayloadType::from_byte(1), PayloadType::Warning);
+        assert_eq!(PayloadType::from_byte(9), PayloadType::Unknown(9));
+    }
+
+    #[test]
+    fn test_filter_levels() {
+        assert!(PayloadType::Fatal.level() > PayloadType::Error.level());
+        assert!(PayloadType::Trace.level() < PayloadType::Debug.level());
+        assert_eq!(PayloadType::Unknown(9).level(), Level::Info);
+        assert_eq!(Level::default(), Level::Trace);
+    }
+
+    #[test]
+    fn test_level_names() {
+        let levels: Vec<TagName> = ["0x09=Notice:blue", "4=Verbose", "2 = Oops : bright-red"]
+            .iter()
+            .map(|level| level.parse().unwrap())
+            .collect();
+        assert_eq!(
+            level_tag(PayloadType::Unknown(9), &levels),
+            "\x1b[0m\x1b[34m[Notice]\x1b[0m "
+        );
+        assert_eq!(
+            level_tag(PayloadType::Info, &levels),
+            "\x1b[0m\x1b[32m[Verbose]\x1b[0m "
+        );
+        assert_eq!(
+            level_tag(PayloadType::Error, &levels),
+            "\x1b[0m\x1b[1;91m[Oops]\x1b[0m "
+        );
+        assert_eq!(
+            level_tag(PayloadType::Unknown(7), &levels),
+            "\x1b[0m\x1b[37m[Unknown 7]\x1b[0m "
+        );
+
+        assert!("Notice".parse::<TagName>().is_err());
+        assert!("300=Notice".parse::<TagName>().is_err());
+        assert!("6=Notice:purple".parse::<TagName>().is_err());
+    }
+
+    #[test]
+    fn test_firmware_encoder() {
+        let formats = FormatTable::new(b"boot\0temp=%d.%02u %s\0".to_vec());
+        let mut arg_buf = [0; 16];
+        let mut args = tlog_frame::Args::new(&mut arg_buf);
+        args.i32(-2).unwrap().u32(5).unwrap().str("ok").unwrap();
+
+        let mut buf = [0; 64];
+        let header = Header {
+            sequence: 7,
+            ticks: 1_000,
+            ..Header::new(payload_type::WARNING, FrameVersion::V4)
+        };
+        let len = header
+            .encode_deferred(5, args.as_bytes(), &mut buf)
+            .unwrap();
+        let tlog = TLog::from_be_bytes(&buf[..len]).unwrap();
+        assert_eq!(
+            (tlog.payload_type(), tlog.sequence(), tlog.ticks()),
+            (PayloadType::Warning, Some(7), Some(1_000))
+        );
+        let Payload::Deferred { index, args } = tlog.payload() else {
+            panic!("{tlog:?} isn't deferred");
+        };
+        assert_eq!(deferred_text(*index, args, Some(&formats)), "temp=-2.05 ok");
+    }
+
+    #[test]
+    fn test_crc16() {
+        assert_eq!(crc16(b"123456789"), 0x29B1);
+        assert_eq!(crc16(b""), 0xFFFF);
+    }
+
+    #[test]
+    fn test_deferred() {
+        let tlog = TLog::deferred(5, vec![0, 0, 0, 42], PayloadType::Warning);
+        let packet = tlog.to_packet_version(FrameVersion::V2).unwrap();
+        assert_eq!(&packet[..5], &[MARKER, 0, 6, 1, 0x82]);
+        assert_eq!(TLog::from_be_bytes(&packet), Ok(tlog));
+
+        let formats = FormatTable::new(b"boot\0temp=%d C\0".to_vec());
+        assert_eq!(
+            deferred_text(5, &[0, 0, 0, 42], Some(&formats)),
+            "temp=42 C"
+        );
+        assert_eq!(deferred_text(5, &[0, 42], None), "<fmt #5> 00 2a");
+        assert!(deferred_text(5, &[0, 42], Some(&formats)).starts_with("<fmt #5: "));
+
+        // The type byte is free to use, deferred payloads are flagged in the version byte
+        let unknown = TLog::new(String::new(), PayloadType::Unknown(0x90));
+        assert_eq!(
+            TLog::from_be_bytes(&unknown.to_packet().unwrap()),
+            Ok(unknown)
+        );
+        assert_eq!(
+            TLog::from_be_bytes(&[MARKER, 0, 1, 0, 0x81, 7]),
+            Err(TLogError::NoFormatIndex)
+        );
+    }
+
+    #[test]
+    fn test_v2_crc_mismatch() {
+        let tlog = TLog::new("ok".to_owned(), PayloadType::Debug);
+        let packet = tlog.to_packet_version(FrameVersion::V2).unwrap();
+        assert_eq!(packet[4], 2);
+        assert_eq!(packet.len(), HEADER_LENGTH + 2 + CRC_LENGTH);
+
+        let mut corrupt = packet.clone();
+        corrupt[5] = b'k';
+        assert!(matches!(
+            TLog::from_be_bytes(&corrupt),
+            Err(TLogError::CrcMismatch { .. })
+        ));
+
+        // A v2 frame cut short by a reset, with a v1 frame within its claimed length
+        let data = [&packet[..6], &frame("next", PayloadType::Error), b"\x00"].concat();
+        let mut decoder = decoder();
+        let events = decode_chunks(&mut decoder, &[&data]);
+        assert!(matches!(
+            events[0],
+            TLogEvent::Error(FramingError::Invalid(TLogError::CrcMismatch { .. }))
+        ));
+        assert_eq!(
+            events[1..],
+            [
+                TLogEvent::Error(FramingError::Garbage(5)),
+                TLogEvent::Frame(TLog::new("next".to_owned(), PayloadType::Error)),
+                TLogEvent::Error(FramingError::Garbage(1)),
+            ]
+        );
+        assert_eq!(decoder.counts().crc_failures, 1);
+    }
+
+    #[test]
+    fn test_sequence_issues() {
+        let numbered = |sequence| {
+            TLog::new("n".to_owned(), PayloadType::Info)
+                .with_sequence(sequence)
+                .to_packet_version(FrameVersion::V3)
+                .unwrap()
+        };
+        let data: Vec<u8> = [10, 11, 14, 14, 3, 0, 65535]
+            .into_iter()
+            .flat_map(numbered)
+            .collect();
+        let mut decoder = decoder();
+        let issues: Vec<SequenceIssue> = decode_chunks(&mut decoder, &[&data])
+            .into_iter()
+            .filter_map(|event| match event {
+                TLogEvent::Sequence(issue) => Some(issue),
+                _ => None,
+            })
+            .collect();
+        assert_eq!(
+            issues,
+            [
+                SequenceIssue::Gap {
+                    expected: 12,
+                    received: 14
+                },
+                SequenceIssue::Duplicate(14),
+                SequenceIssue::Reset {
+                    expected: 15,
+                    received: 3
+                },
+                SequenceIssue::Reset {
+                    expected: 4,
+                    received: 0
+                },
+                SequenceIssue::Reset {
+                    expected: 1,
+                    received: 65535
+                },
+            ]
+        );
+        let counts = decoder.counts();
+        assert_eq!((counts.lost, counts.duplicates, counts.resets), (2, 1, 3));
+    }
+
+    #[test]
+    fn test_channel_names() {
+        let names: Vec<TagName> = ["1=radio:blue", "2=Power Mgmt"]
+            .iter()
+            .map(|name| name.parse().unwrap())
+            .collect();
+        assert_eq!(channel_name(1, &names), ("radio".to_owned(), Color::Blue));
+        assert_eq!(
+            channel_name(2, &names),
+            ("Power Mgmt".to_owned(), Color::Magenta)
+        );
+        assert_eq!(channel_name(9, &names), ("ch9".to_owned(), Color::Cyan));
+        assert_eq!(file_suffix("Power Mgmt"), "power-mgmt");
+
+        // Version 5 leaves out the fields a device doesn't have, so a stream with only
+        // channels isn't checked for its sequence numbers
+        let data: Vec<u8> = [1, 2, 1]
+            .into_iter()
+            .flat_map(|channel| {
+                TLog::new("tx done".to_owned(), PayloadType::Info)
+                    .with_channel(channel)
+                    .to_packet_version(FrameVersion::V5)
+                    .unwrap()
+            })
+            .collect();
+        let events = decode_chunks(&mut decoder(), &[&data]);
+        assert_eq!(events.len(), 3);
+        for event in events {
+            let TLogEvent::Frame(tlog) = event else {
+                panic!("{event:?} instead of a frame");
+            };
+            assert_eq!((tlog.sequence(), tlog.ticks()), (None, None));
+        }
+
+        let mut packet = TLog::new("x".to_owned(), PayloadType::Info)
+            .to_packet_version(FrameVersion::V5)
+            .unwrap();
+        packet[HEADER_LENGTH] = 0x08;
+        let (frame, trailer) = packet.split_at_mut(HEADER_LENGTH + 2);
+        trailer.copy_from_slice(&crc16(frame).to_be_bytes());
+        assert_eq!(
+            TLog::from_be_bytes(&packet),
+            Err(TLogError::BadFields(0x08))
+        );
+    }
+
+    #[test]
+    fn test_commands() {
+        let start = Local::now();
+        let at = |millis| start + chrono::Duration::milliseconds(millis);
+
+        let mut commands = PendingCommands::default();
+        let version = commands.next("version");
+        let reboot = commands.next("reboot");
+        assert_eq!((version.sequence(), reboot.sequence()), (Some(0), Some(1)));
+        assert_eq!(version.kind(), FrameKind::Command);
+        commands.sent(0, "version".to_owned(), at(0));
+        commands.sent(1, "reboot".to_owned(), at(5));
+
+        assert_eq!(
+            commands.reply(Some(1), at(12)),
+            "reply to #1 \"reboot\" after 7 ms"
+        );
+        assert_eq!(
+            commands.reply(Some(1), at(13)),
+            "reply to unknown command #1"
+        );
+        // Unnumbered replies answer the oldest command
+        assert_eq!(
+            commands.reply(None, at(20)),
+            "reply to #0 \"version\" after 20 ms"
+        );
+        assert_eq!(commands.reply(None, at(21)), "reply to no command");
+        assert_eq!(commands.to_string(), "Commands sent: 2, answered: 2");
+
+        // Replies are numbered after commands, not in the stream of the device
+        let data: Vec<u8> = [
+            (FrameKind::Log, 10),
+            (FrameKind::Reply, 0),
+            (FrameKind::Log, 11),
+        ]
+        .into_iter()
+        .flat_map(|(kind, sequence)| {
+            TLog::new("n".to_owned(), PayloadType::Info)
+                .with_kind(kind)
+                .with_sequence(sequence)
+                .to_packet_version(FrameVersion::V3)
+                .unwrap()
+        })
+        .collect();
+        let events = decode_chunks(&mut decoder(), &[&data]);
+        assert!(!events
+            .iter()
+            .any(|event| matches!(event, TLogEvent::Sequence(_))));
+    }
+
+    #[test]
+    fn test_device_clock() {
+        let start = Local::now();
+        let at = |micros| start + chrono::Duration::microseconds(micros);
+
+        let mut clock = DeviceClock::new(1_000, false);
+        assert_eq!(clock.observe(1_500, at(0)), "1.500000");
+        assert_eq!(clock.observe(u32::MAX, at(10)), "4294967.295000");
+        // Rolled over
+        assert_eq!(clock.observe(4, at(20)), "4294967.300000");
+        // Started counting again
+        assert_eq!(clock.observe(2, at(30)), "0.002000");
+
+        let mut clock = DeviceClock::new(1_000_000, true);
+        let first = clock.observe(5_000_000, at(0));
+        assert_eq!(first, at(0).format("%H:%M:%S%.6f").to_string());
+        // The device counted 10 s while the host saw 10.001 s
+        clock.observe(15_000_000, at(10_001_000));
+        assert_eq!(
+            clock.drift().unwrap(),
+            "Device clock drift: -1.000 ms over 10.0 s (-100.0 ppm)"
+        );
+    }
+
+    proptest! {
+        #[test]
+        fn test_round_trip(
+            payload in ".{0,300}",
+            payload_type: u8,
+            deferred: Option<u16>,
+            kind in 0..3u8,
+            version in 1..=5u8,
+            present in 0..=fields::ALL,
+            sequence: u16,
+            ticks: u32,
+            channel: u8,
+        ) {
+            let payload_type = PayloadType::from_byte(payload_type);
+            let version = FrameVersion::from_byte(version).unwrap();
+            let mut tlog = match deferred {
+                Some(index) => TLog::deferred(index, payload.into_bytes(), payload_type),
+                None => TLog::new(payload, payload_type),
+            };
+            tlog = tlog.with_kind([FrameKind::Log, FrameKind::Command, FrameKind::Reply][usize::from(kind)]);
+            let present = version.fields().unwrap_or(present);
+            if present & fields::SEQUENCE != 0 {
+                tlog = tlog.with_sequence(sequence);
+            }
+            if present & fields::TICKS != 0 {
+                tlog = tlog.with_ticks(ticks);
+            }
+            if present & fields::CHANNEL != 0 {
+                tlog = tlog.with_channel(channel);
+            }
+            let packet = tlog.to_packet_version(version).unwrap();
+            prop_assert_eq!(TLog::from_be_bytes(&packet), Ok(tlog));
+        }
+
+        #[test]
+        fn test_parse_never_panics(data in proptest::collection::vec(any::<u8>(), 0..64)) {
+            let _ = TLog::from_be_bytes(&data);
+            for encoding in [Encoding::Lossy, Encoding::Hex] {
+                let _ = TLog::decode(&data, encoding);
+            }
+        }
+
+        #[test]
+        fn test_decoder_never_panics(data in proptest::collection::vec(any::<u8>(), 0..256), split in 0..256usize) {
+            let (first, second) = data.split_at(split.min(data.len()));
+            let mut decoder = TLogDecoder::new(16, Overflow::Truncate, Duration::from_secs(5));
+            decode_chunks(&mut decoder, &[first, second]);
+        }
+    }
 }
@@ -1,9 +1,17 @@
+use chrono::format::{Item, StrftimeItems};
 use chrono::Timelike;
-use chrono::{Datelike, Local};
+use chrono::{DateTime, Datelike, Local};
+use crossterm::style::Stylize;
+use inquire::{CustomType, InquireError};
+use std::fs::{create_dir_all, File, OpenOptions};
+use std::path::Path;
 
 pub fn generate_timestamp() -> String {
-    let now = Local::now();
+    format_timestamp(Local::now())
+}
 
+/// Formats `now` the way [`generate_timestamp`] does.
+pub fn format_timestamp(now: DateTime<Local>) -> String {
     format!(
         "{RESET}[{GREEN}{:04}-{:02}-{:02} {:02}:{:02}:{:02}.{:03}{RESET}] ",
         now.year(),
@@ -17,3 +25,86 @@ pub fn generate_timestamp() -> String {
         GREEN = "\x1b[32m",
     )
 }
+
+/// Asks for the baud rate, `None` if the user interrupts.
+pub fn get_baud() -> Option<u32> {
+    loop {
+        match CustomType::new("What is the baud rate?:")
+            .with_error_message("Please type a valid number")
+            .with_help_message("esc for default")
+            .prompt_skippable()
+        {
+            Ok(ans) => break Some(ans.unwrap_or(115200)),
+            Err(InquireError::OperationInterrupted) => break None,
+            Err(_) => eprintln!("{}", "Please type a correct value".red().slow_blink()),
+        }
+    }
+}
+
+/// Asks for the output file name, `Some(None)` when the user skips writing a file
+/// and `None` if the user interrupts.
+pub fn get_output() -> Option<Option<String>> {
+    loop {
+        match CustomType::new("What is the output file name?:")
+            .with_error_message("Please type a valid file name")
+            .with_help_message("esc to skip outputing to a file")
+            .prompt_skippable()
+        {
+            Ok(ans) => break Some(ans),
+            Err(InquireError::OperationInterrupted) => break None,
+            Err(_) => eprintln!("{}", "Please type a correct value".red().slow_blink()),
+        }
+    }
+}
+
+/// Expands strftime specifiers such as `%Y-%m-%d` in an output file name, so one
+/// saved profile can write a new file per session. Names with invalid specifiers
+/// are used as they are.
+pub fn expand_output_name(name: &str) -> String {
+    let items = StrftimeItems::new(name).collect::<Vec<_>>();
+    if items.iter().any(|item| matches!(item, Item::Error)) {
+        return name.to_owned();
+    }
+
+    Local::now()
+        .format_with_items(items.into_iter())
+        .to_string()
+}
+
+/// Prefixes `line` with the current timestamp and ends it with a newline, unless
+/// it already ends with one.
+pub fn timestamped(line: &[u8]) -> Vec<u8> {
+    timestamped_at(line, Local::now())
+}
+
+/// Like [`timestamped`], with the timestamp of `time` instead of now.
+pub fn timestamped_at(line: &[u8], time: DateTime<Local>) -> Vec<u8> {
+    let timestamp = format_timestamp(time).into_bytes();
+
+    let mut data = Vec::with_capacity(timestamp.len() + line.len() + 1);
+    data.extend_from_slice(&timestamp);
+    data.extend_from_slice(line);
+    if !line.ends_with(b"\n") {
+        data.push(b'\n');
+    }
+    data
+}
+
+/// Opens `{dir}/{output}.txt` for appending, creating `dir` if needed.
+pub fn open_output(dir: &str, output: &str) -> File {
+    if !Path::new(dir).exists() {
+        create_dir_all(dir).expect("Unable to create dir");
+    }
+
+    match OpenOptions::new()
+        .append(true)
+        .create(true)
+        .open(format!("{dir}/{output}.txt"))
+    {
+        Ok(file) => file,
+        Err(e) => {
+            eprintln!("Failed to open \"{}\". Error: {}", output, e);
+            ::std::process::exit(1);
+        }
+    }
+}
@@ -0,0 +1,90 @@
+use std::fs::File;
+use std::io::{BufWriter, Write};
+use std::sync::mpsc::{self, SyncSender};
+use std::thread::{self, JoinHandle};
+
+use crate::console;
+
+/// Most bytes written to the terminal and the file in one go.
+const MAX_BATCH: usize = 1 << 20;
+/// Most lines waiting to be written, further lines wait until there is room.
+const MAX_QUEUED: usize = 16 * 1024;
+
+/// Prints captured lines and appends them to the output file on its own thread,
+/// so a slow terminal or disk never holds up reading the port.
+///
+/// Lines queued while the previous batch was written go out together, with a
+/// single flush. Once [`MAX_QUEUED`] lines are waiting, writing blocks rather than
+/// holding more in memory, and the port's own buffer holds the data meanwhile.
+/// Dropping the writer waits for everything queued to be written.
+pub struct OutputWriter {
+    tx: Option<SyncSender<Vec<u8>>>,
+    handle: Option<JoinHandle<()>>,
+}
+
+impl OutputWriter {
+    /// Starts the writer thread, appending to `file` if set and printing to the
+    /// terminal if `echo` is.
+    pub fn spawn(file: Option<File>, echo: bool) -> Self {
+        let (tx, rx) = mpsc::sync_channel::<Vec<u8>>(MAX_QUEUED);
+
+        let handle = thread::spawn(move || {
+            let mut file = file.map(BufWriter::new);
+
+            while let Ok(data) = rx.recv() {
+                let mut batch = data;
+                let mut text = String::new();
+                if echo {
+                    push_text(&mut text, &batch);
+                }
+                while batch.len() < MAX_BATCH {
+                    let Ok(data) = rx.try_recv() else {
+                        break;
+                    };
+                    if echo {
+                        push_text(&mut text, &data);
+                    }
+                    batch.extend_from_slice(&data);
+                }
+
+                if !text.is_empty() {
+                    console::print(&text);
+                }
+                if let Some(file) = &mut file {
+                    if let Err(e) = file.write_all(&batch).and_then(|_| file.flush()) {
+                        eprintln!("Failed to write the output file. Error: {e}");
+                        ::std::process::exit(1);
+                    }
+                }
+            }
+        });
+
+        Self {
+            tx: Some(tx),
+            handle: Some(handle),
+        }
+    }
+
+    /// Queues `data` to be written, waiting for room if the queue is full.
+    pub fn write(&self, data: Vec<u8>) {
+        if let Some(tx) = &self.tx {
+            let _ = tx.send(data);
+        }
+    }
+}
+
+impl Drop for OutputWriter {
+    fn drop(&mut self) {
+        self.tx.take();
+        if let Some(handle) = self.handle.take() {
+            let _ = handle.join();
+        }
+    }
+}
+
+fn push_text(text: &mut String, data: &[u8]) {
+    match std::str::from_utf8(data) {
+        Ok(string) => text.push_str(string),
+        Err(_) => eprintln!("Bytes are not valid UTF-8, see --encoding to show them"),
+    }
+}
@@ -0,0 +1,57 @@
+/* Generated from the tlog-frame-ffi crate by its tests, do not edit. */
+#ifndef TLOG_FRAME_H
+#define TLOG_FRAME_H
+
+#include <stddef.h>
+#include <stdint.h>
+
+#define TLOG_MARKER 0x1A
+#define TLOG_HEADER_LENGTH 5
+#define TLOG_CRC_LENGTH 2
+#define TLOG_MAX_PREFIX_LENGTH 8
+#define TLOG_MAX_FRAME_LENGTH 65550
+#define TLOG_FLAG_DEFERRED 0x80
+#define TLOG_FLAG_COMMAND 0x40
+#define TLOG_FLAG_REPLY 0x20
+#define TLOG_FIELD_SEQUENCE 0x01
+#define TLOG_FIELD_TICKS 0x02
+#define TLOG_FIELD_CHANNEL 0x04
+
+enum tlog_payload_type {
+    TLOG_TYPE_DEBUG = 0,
+    TLOG_TYPE_WARNING = 1,
+    TLOG_TYPE_ERROR = 2,
+    TLOG_TYPE_TRACE = 3,
+    TLOG_TYPE_INFO = 4,
+    TLOG_TYPE_FATAL = 5,
+};
+
+enum tlog_version {
+    TLOG_V1 = 1, /* prefix 0 bytes, trailer 0 bytes */
+    TLOG_V2 = 2, /* prefix 0 bytes, trailer 2 bytes */
+    TLOG_V3 = 3, /* prefix 2 bytes, trailer 2 bytes */
+    TLOG_V4 = 4, /* prefix 6 bytes, trailer 2 bytes */
+    TLOG_V5 = 5, /* prefix a byte of TLOG_FIELD_* then those fields, trailer 2 bytes */
+};
+
+/* Write a frame to buf and return its length, 0 if it can't be encoded or buf is
+ * too small. flags is 0 or one of TLOG_FLAG_COMMAND and TLOG_FLAG_REPLY. fields
+ * are the TLOG_FIELD_* sent by TLOG_V5, the other versions send the fields of
+ * their layout and ignore the rest. */
+size_t tlog_encode(uint8_t *buf, size_t buf_len, uint8_t version, uint8_t flags,
+                   uint8_t fields, uint8_t payload_type, uint16_t sequence,
+                   uint32_t ticks, uint8_t channel, const uint8_t *payload,
+                   size_t payload_len);
+
+/* Like tlog_encode, with the deferred payload of the format string at index in
+ * the .tlog_fmt section followed by its big endian arguments. */
+size_t tlog_encode_deferred(uint8_t *buf, size_t buf_len, uint8_t version,
+                            uint8_t flags, uint8_t fields, uint8_t payload_type,
+                            uint16_t sequence, uint32_t ticks, uint8_t channel,
+                            uint16_t index, const uint8_t *args, size_t args_len);
+
+/* CRC-16/CCITT-FALSE of data, as sent after the payload of version 2 frames and
+ * later. */
+uint16_t tlog_crc16(const uint8_t *data, size_t len);
+
+#endif /* TLOG_FRAME_H */
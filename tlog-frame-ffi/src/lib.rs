@@ -0,0 +1,364 @@
+//! C functions around the [`Header`] of [`tlog_frame`], built as a static library for
+//! firmware, declared with the constants of the frame layout in
+//! `include/tlog_frame.h`. Each one returns the length of the frame written to `buf`,
+//! or 0 if it couldn't be encoded: an unknown version, flags or fields, a payload too
+//! long or a buffer too small.
+//!
+//! The header is generated by the tests of the crate, run them with
+//! `TLOG_FRAME_BLESS=1` after changing it or the layout. On bare metal targets the
+//! library doesn't use the standard library, and those targets abort on panic.
+#![cfg_attr(target_os = "none", no_std)]
+
+use core::slice;
+
+use tlog_frame::{fields, flags, FrameVersion, Header};
+
+/// Only reached on a bug in the encoder, which doesn't panic on any input.
+#[cfg(target_os = "none")]
+#[panic_handler]
+fn panic(_: &core::panic::PanicInfo) -> ! {
+    loop {
+        core::hint::spin_loop();
+    }
+}
+
+/// Defines C functions with the prototypes written for them in `include/tlog_frame.h`,
+/// taken from the same signatures so the header can't drift from the functions. The
+/// `c_comment` of a function comes before its prototype.
+macro_rules! c_functions {
+    ($(
+        $(#[doc = $doc:literal])*
+        #[c_comment = $comment:literal]
+        pub unsafe extern "C" fn $name:ident($($arg:ident: $ty:ty),* $(,)?) -> $ret:ty $body:block
+    )*) => {
+        $(
+            $(#[doc = $doc])*
+            #[no_mangle]
+            pub unsafe extern "C" fn $name($($arg: $ty),*) -> $ret $body
+        )*
+
+        /// The functions above, for the header.
+        #[cfg(test)]
+        const FUNCTIONS: &[tests::CFunction] = &[$(tests::CFunction {
+            comment: $comment,
+            name: stringify!($name),
+            ret: stringify!($ret),
+            args: &[$((stringify!($arg), stringify!($ty))),*],
+        }),*];
+    };
+}
+
+/// `ptr` and `len` as a slice, empty for a null pointer.
+unsafe fn bytes<'a>(ptr: *const u8, len: usize) -> &'a [u8] {
+    match ptr.is_null() {
+        true => &[],
+        false => slice::from_raw_parts(ptr, len),
+    }
+}
+
+fn header(
+    version: u8,
+    frame_flags: u8,
+    frame_fields: u8,
+    payload_type: u8,
+    sequence: u16,
+    ticks: u32,
+    channel: u8,
+) -> Option<Header> {
+    let kind = flags::COMMAND | flags::REPLY;
+    if frame_flags & !kind != 0 || frame_flags == kind || frame_fields & !fields::ALL != 0 {
+        return None;
+    }
+    Some(Header {
+        payload_type,
+        version: FrameVersion::from_byte(version)?,
+        flags: frame_flags,
+        fields: frame_fields,
+        sequence,
+        ticks,
+        channel,
+    })
+}
+
+c_functions! {
+    /// Writes a frame with `payload` to `buf`, see the module.
+    ///
+    /// # Safety
+    ///
+    /// `buf` must be valid for writes of `buf_len` bytes and `payload` for reads of
+    /// `payload_len` bytes, or null with a length of 0.
+    #[c_comment = "Write a frame to buf and return its length, 0 if it can't be encoded or \
+        buf is too small. flags is 0 or one of TLOG_FLAG_COMMAND and TLOG_FLAG_REPLY. \
+        fields are the TLOG_FIELD_* sent by TLOG_V5, the other versions send the fields \
+        of their layout and ignore the rest."]
+    pub unsafe extern "C" fn tlog_encode(
+        buf: *mut u8,
+        buf_len: usize,
+        version: u8,
+        flags: u8,
+        fields: u8,
+        payload_type: u8,
+        sequence: u16,
+        ticks: u32,
+        channel: u8,
+        payload: *const u8,
+        payload_len: usize,
+    ) -> usize {
+        let Some(header) = header(
+            version,
+            flags,
+            fields,
+            payload_type,
+            sequence,
+            ticks,
+            channel,
+        ) else {
+            return 0;
+        };
+        if buf.is_null() {
+            return 0;
+        }
+        let buf = slice::from_raw_parts_mut(buf, buf_len);
+        header.encode(bytes(payload, payload_len), buf).unwrap_or(0)
+    }
+
+    /// Writes a frame with a deferred payload to `buf`, see the module and
+    /// [`Header::encode_deferred`].
+    ///
+    /// # Safety
+    ///
+    /// `buf` must be valid for writes of `buf_len` bytes and `args` for reads of
+    /// `args_len` bytes, or null with a length of 0.
+    #[c_comment = "Like tlog_encode, with the deferred payload of the format string at \
+        index in the .tlog_fmt section followed by its big endian arguments."]
+    pub unsafe extern "C" fn tlog_encode_deferred(
+        buf: *mut u8,
+        buf_len: usize,
+        version: u8,
+        flags: u8,
+        fields: u8,
+        payload_type: u8,
+        sequence: u16,
+        ticks: u32,
+        channel: u8,
+        index: u16,
+        args: *const u8,
+        args_len: usize,
+    ) -> usize {
+        let Some(header) = header(
+            version,
+            flags,
+            fields,
+            payload_type,
+            sequence,
+            ticks,
+            channel,
+        ) else {
+            return 0;
+        };
+        if buf.is_null() {
+            return 0;
+        }
+        let buf = slice::from_raw_parts_mut(buf, buf_len);
+        header
+            .encode_deferred(index, bytes(args, args_len), buf)
+            .unwrap_or(0)
+    }
+
+    /// CRC-16/CCITT-FALSE of `data`, see [`tlog_frame::crc16`].
+    ///
+    /// # Safety
+    ///
+    /// `data` must be valid for reads of `len` bytes, or null with a length of 0.
+    #[c_comment = "CRC-16/CCITT-FALSE of data, as sent after the payload of version 2 \
+        frames and later."]
+    pub unsafe extern "C" fn tlog_crc16(data: *const u8, len: usize) -> u16 {
+        tlog_frame::crc16(bytes(data, len))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tlog_frame::{
+        payload_type, CRC_LENGTH, HEADER_LENGTH, MARKER, MAX_FRAME_LENGTH, MAX_PREFIX_LENGTH,
+    };
+
+    /// Columns the comments and prototypes of the header are wrapped at.
+    const WIDTH: usize = 84;
+
+    /// A function of the crate as declared in the header, see `c_functions!`.
+    pub(super) struct CFunction {
+        pub comment: &'static str,
+        pub name: &'static str,
+        pub ret: &'static str,
+        /// Name and Rust type of each argument.
+        pub args: &'static [(&'static str, &'static str)],
+    }
+
+    impl CFunction {
+        /// The C type of a Rust type in the signature of a function.
+        fn c_type(rust: &str) -> &'static str {
+            match rust {
+                "u8" => "uint8_t",
+                "u16" => "uint16_t",
+                "u32" => "uint32_t",
+                "usize" => "size_t",
+                "*mut u8" => "uint8_t *",
+                "*const u8" => "const uint8_t *",
+                _ => panic!("No C type for {rust}, add it to CFunction::c_type"),
+            }
+        }
+
+        /// The comment and prototype of the function, wrapped at [`WIDTH`].
+        fn declaration(&self) -> String {
+            let mut lines = Vec::new();
+            let mut line = String::from("/*");
+            for word in self.comment.split_whitespace() {
+                if line.len() + 1 + word.len() > WIDTH - 3 {
+                    lines.push(std::mem::replace(&mut line, String::from(" *")));
+                }
+                line.push(' ');
+                line.push_str(word);
+            }
+            lines.push(line + " */");
+
+            let mut line = format!("{} {}(", Self::c_type(self.ret), self.name);
+            let indent = " ".repeat(line.len());
+            for (i, (name, rust)) in self.args.iter().enumerate() {
+                let c_type = Self::c_type(rust);
+                let space = if c_type.ends_with('*') { "" } else { " " };
+                let end = if i + 1 == self.args.len() { ");" } else { "," };
+                let arg = format!("{c_type}{space}{name}{end}");
+                if i > 0 && line.len() + 1 + arg.len() > WIDTH {
+                    lines.push(std::mem::replace(&mut line, indent.clone()));
+                } else if i > 0 {
+                    line.push(' ');
+                }
+                line.push_str(&arg);
+            }
+            lines.push(line);
+            lines.join("\n") + "\n"
+        }
+    }
+
+    /// `include/tlog_frame.h` as generated from the crate.
+    fn c_header() -> String {
+        let mut header = String::from(
+            "/* Generated from the tlog-frame-ffi crate by its tests, do not edit. */\n\
+             #ifndef TLOG_FRAME_H\n\
+             #define TLOG_FRAME_H\n\
+             \n\
+             #include <stddef.h>\n\
+             #include <stdint.h>\n\
+             \n",
+        );
+        for (name, value) in [
+            ("MARKER", format!("0x{MARKER:02X}")),
+            ("HEADER_LENGTH", HEADER_LENGTH.to_string()),
+            ("CRC_LENGTH", CRC_LENGTH.to_string()),
+            ("MAX_PREFIX_LENGTH", MAX_PREFIX_LENGTH.to_string()),
+            ("MAX_FRAME_LENGTH", MAX_FRAME_LENGTH.to_string()),
+            ("FLAG_DEFERRED", format!("0x{:02X}", flags::DEFERRED)),
+            ("FLAG_COMMAND", format!("0x{:02X}", flags::COMMAND)),
+            ("FLAG_REPLY", format!("0x{:02X}", flags::REPLY)),
+            ("FIELD_SEQUENCE", format!("0x{:02X}", fields::SEQUENCE)),
+            ("FIELD_TICKS", format!("0x{:02X}", fields::TICKS)),
+            ("FIELD_CHANNEL", format!("0x{:02X}", fields::CHANNEL)),
+        ] {
+            header.push_str(&format!("#define TLOG_{name} {value}\n"));
+        }
+
+        header.push_str("\nenum tlog_payload_type {\n");
+        for (name, value) in [
+            ("DEBUG", payload_type::DEBUG),
+            ("WARNING", payload_type::WARNING),
+            ("ERROR", payload_type::ERROR),
+            ("TRACE", payload_type::TRACE),
+            ("INFO", payload_type::INFO),
+            ("FATAL", payload_type::FATAL),
+        ] {
+            header.push_str(&format!("    TLOG_TYPE_{name} = {value},\n"));
+        }
+
+        header.push_str("};\n\nenum tlog_version {\n");
+        for version in [1, 2, 3, 4, 5].map(|byte| FrameVersion::from_byte(byte).unwrap()) {
+            let prefix = match version.fields() {
+                Some(fields) => format!("{} bytes", fields::length(fields)),
+                None => "a byte of TLOG_FIELD_* then those fields".to_owned(),
+            };
+            header.push_str(&format!(
+                "    TLOG_V{0} = {0}, /* prefix {prefix}, trailer {1} bytes */\n",
+                version as u8,
+                version.trailer_length(),
+            ));
+        }
+        header.push_str("};\n\n");
+        let declarations: Vec<String> = FUNCTIONS.iter().map(CFunction::declaration).collect();
+        header.push_str(&declarations.join("\n"));
+        header.push_str("\n#endif /* TLOG_FRAME_H */\n");
+        header
+    }
+
+    #[test]
+    fn test_c_header() {
+        let path = concat!(env!("CARGO_MANIFEST_DIR"), "/include/tlog_frame.h");
+        if std::env::var_os("TLOG_FRAME_BLESS").is_some() {
+            std::fs::write(path, c_header()).unwrap();
+        }
+        assert_eq!(
+            std::fs::read_to_string(path).unwrap(),
+            c_header(),
+            "include/tlog_frame.h is out of date, run the tests with TLOG_FRAME_BLESS=1"
+        );
+    }
+
+    #[test]
+    fn test_ffi() {
+        let mut buf = [0; 16];
+        let payload = b"up";
+        let encode = |buf: &mut [u8], version, flags, fields| unsafe {
+            tlog_encode(
+                buf.as_mut_ptr(),
+                buf.len(),
+                version,
+                flags,
+                fields,
+                4,
+                9,
+                0,
+                3,
+                payload.as_ptr(),
+                payload.len(),
+            )
+        };
+        let len = encode(&mut buf, 3, 0, 0);
+        assert_eq!(&buf[..len - 2], &[0x1A, 0, 2, 4, 3, 0, 9, b'u', b'p']);
+        let len = encode(&mut buf, 5, 0, fields::CHANNEL);
+        assert_eq!(&buf[..len - 2], &[0x1A, 0, 2, 4, 5, 0x04, 3, b'u', b'p']);
+
+        assert_eq!(encode(&mut buf, 9, 0, 0), 0, "unknown version");
+        assert_eq!(encode(&mut buf[..6], 1, 0, 0), 0, "buffer too small");
+        assert_eq!(encode(&mut buf, 1, 0x60, 0), 0, "command and reply");
+        assert_eq!(encode(&mut buf, 5, 0, 0x08), 0, "unknown field");
+
+        let len = unsafe {
+            tlog_encode_deferred(
+                buf.as_mut_ptr(),
+                buf.len(),
+                1,
+                flags::REPLY,
+                0,
+                0,
+                0,
+                0,
+                0,
+                3,
+                core::ptr::null(),
+                0,
+            )
+        };
+        assert_eq!(&buf[..len], &[0x1A, 0, 2, 0, 0xA1, 0, 3]);
+        assert_eq!(unsafe { tlog_crc16(b"123456789".as_ptr(), 9) }, 0x29B1);
+    }
+}
@@ -0,0 +1,99 @@
+use crate::EncodeError;
+
+/// Writes the arguments of a deferred payload into a caller buffer, big endian and
+/// in the sizes the host expects for each conversion of the format string:
+/// - `%hhd`, `%hhu`, `%c` one byte, `%hd`, `%hu` two, `%d`, `%u`, `%x`, `%p` four
+///   and `%lld`, `%llu` eight;
+/// - `%f` an f32 and `%lf` an f64;
+/// - `%s` a byte with the length of the string followed by its bytes.
+pub struct Args<'a> {
+    buf: &'a mut [u8],
+    len: usize,
+}
+
+impl<'a> Args<'a> {
+    pub fn new(buf: &'a mut [u8]) -> Self {
+        Self { buf, len: 0 }
+    }
+
+    /// The arguments written so far.
+    pub fn as_bytes(&self) -> &[u8] {
+        &self.buf[..self.len]
+    }
+
+    /// Appends `bytes` as they are.
+    pub fn bytes(&mut self, bytes: &[u8]) -> Result<&mut Self, EncodeError> {
+        let end = self.len + bytes.len();
+        let Some(dest) = self.buf.get_mut(self.len..end) else {
+            return Err(EncodeError::BufferTooSmall(end));
+        };
+        dest.copy_from_slice(bytes);
+        self.len = end;
+        Ok(self)
+    }
+
+    pub fn u8(&mut self, value: u8) -> Result<&mut Self, EncodeError> {
+        self.bytes(&[value])
+    }
+
+    pub fn u16(&mut self, value: u16) -> Result<&mut Self, EncodeError> {
+        self.bytes(&value.to_be_bytes())
+    }
+
+    pub fn u32(&mut self, value: u32) -> Result<&mut Self, EncodeError> {
+        self.bytes(&value.to_be_bytes())
+    }
+
+    pub fn u64(&mut self, value: u64) -> Result<&mut Self, EncodeError> {
+        self.bytes(&value.to_be_bytes())
+    }
+
+    pub fn i8(&mut self, value: i8) -> Result<&mut Self, EncodeError> {
+        self.bytes(&value.to_be_bytes())
+    }
+
+    pub fn i16(&mut self, value: i16) -> Result<&mut Self, EncodeError> {
+        self.bytes(&value.to_be_bytes())
+    }
+
+    pub fn i32(&mut self, value: i32) -> Result<&mut Self, EncodeError> {
+        self.bytes(&value.to_be_bytes())
+    }
+
+    pub fn i64(&mut self, value: i64) -> Result<&mut Self, EncodeError> {
+        self.bytes(&value.to_be_bytes())
+    }
+
+    pub fn f32(&mut self, value: f32) -> Result<&mut Self, EncodeError> {
+        self.bytes(&value.to_be_bytes())
+    }
+
+    pub fn f64(&mut self, value: f64) -> Result<&mut Self, EncodeError> {
+        self.bytes(&value.to_be_bytes())
+    }
+
+    /// Appends `value` for a `%s`, cut to its first 255 bytes.
+    pub fn str(&mut self, value: &str) -> Result<&mut Self, EncodeError> {
+        let bytes = &value.as_bytes()[..value.len().min(u8::MAX.into())];
+        self.u8(bytes.len() as u8)?.bytes(bytes)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_args() {
+        let mut buf = [0; 16];
+        let mut args = Args::new(&mut buf);
+        args.i32(-2).unwrap().u16(5).unwrap().str("abc").unwrap();
+        assert_eq!(
+            args.as_bytes(),
+            &[0xFF, 0xFF, 0xFF, 0xFE, 0, 5, 3, b'a', b'b', b'c']
+        );
+        assert_eq!(args.u64(1).err(), Some(EncodeError::BufferTooSmall(18)));
+        // Nothing is written by a failed argument
+        assert_eq!(args.as_bytes().len(), 10);
+    }
+}
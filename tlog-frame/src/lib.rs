@@ -0,0 +1,401 @@
+//! Layout of TLog frames and an encoder writing them into a caller buffer, without
+//! the standard library or allocations, for device firmware.
+//!
+//! Every frame starts with a header of [`HEADER_LENGTH`] bytes: the [`MARKER`], the
+//! big endian length of the payload, the payload type and the [`FrameVersion`] with
+//! the [`flags`] of the frame. The prefix of the version comes next, then the payload
+//! and the trailer of the version.
+//!
+//! Firmware written in C links the `tlog-frame-ffi` static library instead, declared
+//! with the constants of this crate in its `include/tlog_frame.h`.
+#![cfg_attr(not(test), no_std)]
+
+pub mod args;
+
+pub use args::Args;
+
+/// First byte of every frame.
+pub const MARKER: u8 = 0x1A;
+/// Bytes in front of the payload of a frame.
+pub const HEADER_LENGTH: usize = 5;
+/// Bytes after the payload of a version 2 frame or later.
+pub const CRC_LENGTH: usize = 2;
+/// Most bytes between the header and the payload, in a version 5 frame with every field.
+pub const MAX_PREFIX_LENGTH: usize = 8;
+/// Longest frame the length field allows.
+pub const MAX_FRAME_LENGTH: usize =
+    u16::MAX as usize + HEADER_LENGTH + MAX_PREFIX_LENGTH + CRC_LENGTH;
+
+/// Bits of the version byte above the version, saying what a frame carries.
+pub mod flags {
+    /// The payload is a format string index and arguments instead of text, see
+    /// [`Header::encode_deferred`](crate::Header::encode_deferred).
+    pub const DEFERRED: u8 = 0x80;
+    /// A command sent by the host to the device, numbered with a sequence number.
+    pub const COMMAND: u8 = 0x40;
+    /// The answer of the device to a command, with the sequence number of the command.
+    pub const REPLY: u8 = 0x20;
+    /// Every flag, the other bits of the version byte are the version.
+    pub const ALL: u8 = DEFERRED | COMMAND | REPLY;
+}
+
+/// Bits of the first byte of a version 5 prefix, saying which fields follow it in
+/// this order.
+pub mod fields {
+    /// A big endian u16 sequence number.
+    pub const SEQUENCE: u8 = 0x01;
+    /// The big endian u32 device clock ticks when the frame was sent.
+    pub const TICKS: u8 = 0x02;
+    /// The channel of the frame, a byte.
+    pub const CHANNEL: u8 = 0x04;
+    /// Every field, the other bits must be 0.
+    pub const ALL: u8 = SEQUENCE | TICKS | CHANNEL;
+
+    /// Bytes of `fields`.
+    pub const fn length(fields: u8) -> usize {
+        let mut length = 0;
+        if fields & SEQUENCE != 0 {
+            length += 2;
+        }
+        if fields & TICKS != 0 {
+            length += 4;
+        }
+        if fields & CHANNEL != 0 {
+            length += 1;
+        }
+        length
+    }
+}
+
+/// Payload type bytes with a meaning on the host, any other byte can be named there.
+pub mod payload_type {
+    pub const DEBUG: u8 = 0;
+    pub const WARNING: u8 = 1;
+    pub const ERROR: u8 = 2;
+    pub const TRACE: u8 = 3;
+    pub const INFO: u8 = 4;
+    pub const FATAL: u8 = 5;
+}
+
+/// CRC-16/CCITT-FALSE of every byte value, for [`crc16`].
+const CRC16_TABLE: [u16; 256] = crc16_table();
+
+/// Layout of a frame, given by the last byte of its header.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+#[repr(u8)]
+pub enum FrameVersion {
+    /// The payload ends the frame.
+    #[default]
+    V1 = 1,
+    /// The payload is followed by a big endian CRC-16/CCITT-FALSE of the header and
+    /// payload.
+    V2 = 2,
+    /// A big endian u16 sequence number comes between the header and the payload,
+    /// with a CRC like in version 2 after the payload.
+    V3 = 3,
+    /// Like version 3, with the big endian u32 device clock ticks when the frame was
+    /// sent after the sequence number.
+    V4 = 4,
+    /// Any of the sequence number, ticks and channel of the frame between the header
+    /// and the payload, after a byte of [`fields`] saying which ones, with a CRC like
+    /// in version 2 after the payload.
+    V5 = 5,
+}
+
+impl FrameVersion {
+    pub const fn from_byte(byte: u8) -> Option<Self> {
+        match byte {
+            1 => Some(Self::V1),
+            2 => Some(Self::V2),
+            3 => Some(Self::V3),
+            4 => Some(Self::V4),
+            5 => Some(Self::V5),
+            _ => None,
+        }
+    }
+
+    /// The [`fields`] every frame of the version sends, `None` for version 5 saying
+    /// which ones in the first byte of its prefix.
+    pub const fn fields(self) -> Option<u8> {
+        match self {
+            Self::V1 | Self::V2 => Some(0),
+            Self::V3 => Some(fields::SEQUENCE),
+            Self::V4 => Some(fields::SEQUENCE | fields::TICKS),
+            Self::V5 => None,
+        }
+    }
+
+    /// Bytes between the header and the payload, with `fields_byte` the first of them
+    /// in version 5. The other versions ignore it.
+    pub const fn prefix_length(self, fields_byte: u8) -> usize {
+        match self.fields() {
+            Some(fields) => fields::length(fields),
+            None => 1 + fields::length(fields_byte),
+        }
+    }
+
+    /// Bytes after the payload.
+    pub const fn trailer_length(self) -> usize {
+        match self {
+            Self::V1 => 0,
+            Self::V2 | Self::V3 | Self::V4 | Self::V5 => CRC_LENGTH,
+        }
+    }
+
+    /// The version and flags of a version byte, `None` if the version is unknown or
+    /// the frame is flagged as both a command and a reply.
+    pub const fn split_byte(byte: u8) -> Option<(Self, u8)> {
+        let both = flags::COMMAND | flags::REPLY;
+        match Self::from_byte(byte & !flags::ALL) {
+            Some(version) if byte & both != both => Some((version, byte & flags::ALL)),
+            _ => None,
+        }
+    }
+
+    /// Bytes of a frame with a payload of `payload_length` bytes, see
+    /// [`FrameVersion::prefix_length`] for `fields_byte`.
+    pub const fn frame_length(self, fields_byte: u8, payload_length: usize) -> usize {
+        HEADER_LENGTH + self.prefix_length(fields_byte) + payload_length + self.trailer_length()
+    }
+}
+
+/// Why a frame couldn't be encoded.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum EncodeError {
+    /// The frame needs a buffer of this many bytes.
+    BufferTooSmall(usize),
+    /// A payload of this many bytes doesn't fit the length field.
+    TooLong(usize),
+}
+
+impl core::fmt::Display for EncodeError {
+    fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
+        match self {
+            Self::BufferTooSmall(len) => write!(f, "Frame needs a buffer of {len} bytes"),
+            Self::TooLong(len) => write!(f, "Payload of {len} bytes is too long for a frame"),
+        }
+    }
+}
+
+/// Everything in a frame but its payload. Fields the version doesn't send are left
+/// out, the ones it sends default to 0.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub struct Header {
+    pub payload_type: u8,
+    pub version: FrameVersion,
+    /// [`flags::COMMAND`] or [`flags::REPLY`] for a frame that isn't logged, the
+    /// deferred flag is set by [`Header::encode_deferred`].
+    pub flags: u8,
+    /// The [`fields`] sent by a version 5 frame, the other versions send the fields
+    /// of their layout.
+    pub fields: u8,
+    pub sequence: u16,
+    pub ticks: u32,
+    pub channel: u8,
+}
+
+impl Header {
+    pub const fn new(payload_type: u8, version: FrameVersion) -> Self {
+        Self {
+            payload_type,
+            version,
+            flags: 0,
+            fields: 0,
+            sequence: 0,
+            ticks: 0,
+            channel: 0,
+        }
+    }
+
+    /// Writes the frame with `payload` to the start of `buf`, returning its length.
+    pub fn encode(&self, payload: &[u8], buf: &mut [u8]) -> Result<usize, EncodeError> {
+        self.encode_parts(0, &[], payload, buf)
+    }
+
+    /// Writes the frame of a deferred payload to the start of `buf`, returning its
+    /// length. The payload is the big endian offset `index` of its format string in the
+    /// `.tlog_fmt` section of the firmware followed by `args`, see [`Args`].
+    pub fn encode_deferred(
+        &self,
+        index: u16,
+        args: &[u8],
+        buf: &mut [u8],
+    ) -> Result<usize, EncodeError> {
+        self.encode_parts(flags::DEFERRED, &index.to_be_bytes(), args, buf)
+    }
+
+    fn encode_parts(
+        &self,
+        flags: u8,
+        head: &[u8],
+        payload: &[u8],
+        buf: &mut [u8],
+    ) -> Result<usize, EncodeError> {
+        let payload_length = head.len() + payload.len();
+        if payload_length > u16::MAX.into() {
+            return Err(EncodeError::TooLong(payload_length));
+        }
+        let fields = match self.version.fields() {
+            Some(fields) => fields,
+            None => self.fields & fields::ALL,
+        };
+        let length = self.version.frame_length(fields, payload_length);
+        let Some(frame) = buf.get_mut(..length) else {
+            return Err(EncodeError::BufferTooSmall(length));
+        };
+
+        let [len_hi, len_lo] = (payload_length as u16).to_be_bytes();
+        frame[..HEADER_LENGTH].copy_from_slice(&[
+            MARKER,
+            len_hi,
+            len_lo,
+            self.payload_type,
+            self.version as u8 | self.flags | flags,
+        ]);
+        let mut end = HEADER_LENGTH;
+        if self.version.fields().is_none() {
+            frame[end] = fields;
+            end += 1;
+        }
+        for (field, part) in [
+            (fields::SEQUENCE, &self.sequence.to_be_bytes()[..]),
+            (fields::TICKS, &self.ticks.to_be_bytes()),
+            (fields::CHANNEL, &[self.channel]),
+        ] {
+            if fields & field != 0 {
+                frame[end..end + part.len()].copy_from_slice(part);
+                end += part.len();
+            }
+        }
+        for part in [head, payload] {
+            frame[end..end + part.len()].copy_from_slice(part);
+            end += part.len();
+        }
+        if self.version.trailer_length() == CRC_LENGTH {
+            let crc = crc16(&frame[..end]);
+            frame[end..].copy_from_slice(&crc.to_be_bytes());
+        }
+        Ok(length)
+    }
+}
+
+const fn crc16_table() -> [u16; 256] {
+    let mut table = [0; 256];
+    let mut i = 0;
+    while i < 256 {
+        let mut crc = (i as u16) << 8;
+        let mut bit = 0;
+        while bit < 8 {
+            crc = if crc & 0x8000 != 0 {
+                (crc << 1) ^ 0x1021
+            } else {
+                crc << 1
+            };
+            bit += 1;
+        }
+        table[i] = crc;
+        i += 1;
+    }
+    table
+}
+
+/// CRC-16/CCITT-FALSE of `data`: polynomial 0x1021, initial value 0xFFFF, not
+/// reflected and without a final XOR.
+pub fn crc16(data: &[u8]) -> u16 {
+    data.iter().fold(0xFFFF, |crc, &byte| {
+        (crc << 8) ^ CRC16_TABLE[usize::from((crc >> 8) as u8 ^ byte)]
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_encode() {
+        let mut buf = [0; 32];
+        let header = Header::new(payload_type::INFO, FrameVersion::V1);
+        assert_eq!(header.encode(b"hi", &mut buf), Ok(7));
+        assert_eq!(&buf[..7], &[MARKER, 0, 2, 4, 1, b'h', b'i']);
+
+        let header = Header {
+            fields: fields::ALL,
+            sequence: 0x0102,
+            ticks: 0x03040506,
+            channel: 7,
+            ..Header::new(payload_type::ERROR, FrameVersion::V5)
+        };
+        let len = header.encode(b"x", &mut buf).unwrap();
+        assert_eq!(
+            &buf[..len - CRC_LENGTH],
+            &[MARKER, 0, 1, 2, 5, 0x07, 1, 2, 3, 4, 5, 6, 7, b'x']
+        );
+        let crc = crc16(&buf[..len - CRC_LENGTH]).to_be_bytes();
+        assert_eq!(&buf[len - CRC_LENGTH..len], &crc);
+
+        let len = header.encode_deferred(0x0A0B, &[1], &mut buf).unwrap();
+        assert_eq!(&buf[..5], &[MARKER, 0, 3, 2, 0x85]);
+        assert_eq!(&buf[13..16], &[0x0A, 0x0B, 1]);
+        assert_eq!(len, 18);
+
+        // Only the fields given are sent
+        let header = Header {
+            fields: fields::CHANNEL,
+            ..header
+        };
+        let len = header.encode(b"x", &mut buf).unwrap();
+        assert_eq!(
+            &buf[..len - CRC_LENGTH],
+            &[MARKER, 0, 1, 2, 5, 0x04, 7, b'x']
+        );
+        let header = Header {
+            fields: 0,
+            ..header
+        };
+        assert_eq!(
+            header.encode(b"x", &mut buf),
+            Ok(HEADER_LENGTH + 2 + CRC_LENGTH)
+        );
+        assert_eq!(buf[HEADER_LENGTH], 0);
+    }
+
+    #[test]
+    fn test_encode_errors() {
+        let header = Header::new(payload_type::DEBUG, FrameVersion::V2);
+        assert_eq!(
+            header.encode(b"hello", &mut [0; 11]),
+            Err(EncodeError::BufferTooSmall(12))
+        );
+        assert_eq!(
+            header.encode(&[0; 0x10000], &mut [0; 16]),
+            Err(EncodeError::TooLong(0x10000))
+        );
+        // Every type byte can be sent, flags are in the version byte
+        assert_eq!(
+            Header::new(0x90, FrameVersion::V1).encode(b"", &mut [0; 16]),
+            Ok(HEADER_LENGTH)
+        );
+    }
+
+    #[test]
+    fn test_split_version_byte() {
+        assert_eq!(FrameVersion::split_byte(3), Some((FrameVersion::V3, 0)));
+        assert_eq!(
+            FrameVersion::split_byte(0x81),
+            Some((FrameVersion::V1, flags::DEFERRED))
+        );
+        assert_eq!(
+            FrameVersion::split_byte(0xA5),
+            Some((FrameVersion::V5, flags::DEFERRED | flags::REPLY))
+        );
+        assert_eq!(FrameVersion::split_byte(0x80), None);
+        assert_eq!(FrameVersion::split_byte(0x63), None);
+        assert_eq!(FrameVersion::split_byte(0x11), None);
+    }
+
+    #[test]
+    fn test_crc16() {
+        assert_eq!(crc16(b"123456789"), 0x29B1);
+        assert_eq!(crc16(b""), 0xFFFF);
+    }
+}